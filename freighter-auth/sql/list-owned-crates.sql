@@ -0,0 +1,4 @@
+select fco.crate
+from freighter_crate_owners fco
+         join freighter_users on freighter_users.id = fco.user_id
+where freighter_users.username = $1;
@@ -25,6 +25,10 @@ pub trait AuthProvider {
     async fn add_owners(&self, token: &str, users: &[&str], crate_name: &str) -> AuthResult<()>;
     /// Remove an owner from a crate.
     async fn remove_owners(&self, token: &str, users: &[&str], crate_name: &str) -> AuthResult<()>;
+    /// List the names of the crates owned by a user.
+    ///
+    /// This is public information, used to filter searches by owner.
+    async fn list_owned_crates(&self, username: &str) -> AuthResult<Vec<String>>;
 
     /// Verify that a user has permission to publish new versions of a crate.
     ///
@@ -35,6 +39,8 @@ pub trait AuthProvider {
     async fn auth_yank(&self, token: &str, crate_name: &str) -> AuthResult<()>;
     /// Verify that a user has permission to unyank versions of a crate.
     async fn auth_unyank(&self, token: &str, crate_name: &str) -> AuthResult<()>;
+    /// Verify that a user has permission to upload documentation for versions of a crate.
+    async fn auth_upload_docs(&self, token: &str, crate_name: &str) -> AuthResult<()>;
 
     /// Verify that a user is allowed to look at the index entry for a given crate.
     ///
@@ -67,4 +73,13 @@ pub trait AuthProvider {
         let _ = token;
         Ok(())
     }
+
+    /// Get the name of the user a token belongs to, for recording who performed an action.
+    ///
+    /// A default implementation is provided which returns [`None`], for backends which don't
+    /// track users.
+    async fn get_username(&self, token: &str) -> AuthResult<Option<String>> {
+        let _ = token;
+        Ok(None)
+    }
 }
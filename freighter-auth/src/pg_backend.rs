@@ -238,6 +238,29 @@ impl AuthProvider for PgAuthProvider {
         self.remove_owners_no_auth(users, crate_name).await
     }
 
+    async fn list_owned_crates(&self, username: &str) -> AuthResult<Vec<String>> {
+        let client = self
+            .pool
+            .get()
+            .await
+            .context("Failed to get auth db client from pool")?;
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/list-owned-crates.sql"))
+            .await
+            .context("Failed to prepare owned crates list statement")?;
+
+        let crates = client
+            .query(&statement, &[&username])
+            .await
+            .context("Failed to list owned crates")?
+            .into_iter()
+            .map(|row| row.get("crate"))
+            .collect();
+
+        Ok(crates)
+    }
+
     async fn publish(&self, token: &str, crate_name: &str) -> AuthResult<()> {
         let crate_owners = self.list_owners_no_auth(crate_name).await?;
 
@@ -259,4 +282,12 @@ impl AuthProvider for PgAuthProvider {
     async fn auth_unyank(&self, token: &str, crate_name: &str) -> AuthResult<()> {
         self.auth_crate_action(token, crate_name).await
     }
+
+    async fn auth_upload_docs(&self, token: &str, crate_name: &str) -> AuthResult<()> {
+        self.auth_crate_action(token, crate_name).await
+    }
+
+    async fn get_username(&self, token: &str) -> AuthResult<Option<String>> {
+        self.get_user_for_token(token).await.map(Some)
+    }
 }
@@ -37,6 +37,10 @@ impl AuthProvider for YesAuthProvider {
         Ok(())
     }
 
+    async fn list_owned_crates(&self, _username: &str) -> AuthResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     async fn publish(&self, _token: &str, _crate_name: &str) -> AuthResult<()> {
         Ok(())
     }
@@ -48,4 +52,8 @@ impl AuthProvider for YesAuthProvider {
     async fn auth_unyank(&self, _token: &str, _crate_name: &str) -> AuthResult<()> {
         Ok(())
     }
+
+    async fn auth_upload_docs(&self, _token: &str, _crate_name: &str) -> AuthResult<()> {
+        Ok(())
+    }
 }
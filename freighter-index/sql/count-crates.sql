@@ -0,0 +1,4 @@
+select count(*) as total
+from crates
+where crates.registry is null
+  and exists(select 1 from crate_versions cv where cv.crate = crates.id)
@@ -0,0 +1,9 @@
+select vd.version,
+       vd.date,
+       vd.downloads
+from crates
+         left join (crate_versions cv join version_downloads vd on vd.version = cv.id and vd.date >= $2)
+                   on cv.crate = crates.id
+where crates.name = $1
+  and crates.registry is null
+order by vd.date, vd.version
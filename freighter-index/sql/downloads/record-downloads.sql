@@ -0,0 +1,19 @@
+with counts as (select cv.id as version, cv.crate, u.downloads
+                from unnest($1::text[], $2::text[], $3::bigint[]) as u(name, version, downloads)
+                         join crates c on c.name = u.name and c.registry is null
+                         join crate_versions cv on cv.crate = c.id and cv.version = u.version),
+     daily as (
+         insert into version_downloads (version, date, downloads)
+             select version, (now() at time zone 'utc')::date, downloads
+             from counts
+             on conflict (version, date) do update
+                 set downloads = version_downloads.downloads + excluded.downloads),
+     versions as (
+         update crate_versions cv
+             set downloads = cv.downloads + counts.downloads
+             from counts
+             where cv.id = counts.version)
+update crates c
+set downloads = c.downloads + totals.downloads
+from (select crate, sum(downloads)::bigint as downloads from counts group by crate) totals
+where c.id = totals.crate
@@ -1,19 +1,38 @@
-select crates.name,
-       crates.description,
-       crates.documentation,
-       crates.homepage,
-       crates.repository,
-       array_agg(cv.version)      as versions,
-       count(dependency),
-       array_agg(distinct c.name) as categories,
-       array_agg(distinct k.name) as keywords
-from crates
-         join crate_versions cv on crates.id = cv.crate
-         left join dependencies d on crates.id = d.dependency
-         left join crate_categories cc on crates.id = cc.crate
-         join categories c on c.id = cc.category
-         left join crate_keywords ck on crates.id = ck.crate
-         join keywords k on k.id = ck.keyword
-where crates.registry is null
-group by crates.name, crates.description, crates.documentation, crates.homepage, crates.repository
-having count(cv.version) > 0
+with listed as (select crates.*,
+                       case $1::text
+                           when 'recent-updates'
+                               then (extract(epoch from crates.updated_at) * 1000000)::bigint
+                           when 'downloads' then crates.downloads
+                           when 'reverse-dependencies' then (select count(distinct cv.crate)
+                                                             from dependencies d
+                                                                      join crate_versions cv on cv.id = d.dependent
+                                                             where d.dependency = crates.id)
+                           else 0
+                           end as sort_key
+                from crates
+                where crates.registry is null
+                  and exists(select 1 from crate_versions cv where cv.crate = crates.id))
+select listed.name,
+       listed.description,
+       listed.documentation,
+       listed.homepage,
+       listed.repository,
+       listed.sort_key,
+       (select array_agg(cv.version)
+        from crate_versions cv
+        where cv.crate = listed.id)                   as versions,
+       coalesce((select array_agg(c.name order by c.name)
+                 from crate_categories cc
+                          join categories c on c.id = cc.category
+                 where cc.crate = listed.id), '{}') as categories,
+       coalesce((select array_agg(k.name order by k.name)
+                 from crate_keywords ck
+                          join keywords k on k.id = ck.keyword
+                 where ck.crate = listed.id), '{}') as keywords
+from listed
+-- keyset pagination, continuing after the last crate of the previous page
+where $2::bigint is null
+   or listed.sort_key < $2
+   or (listed.sort_key = $2 and listed.name > $3)
+order by listed.sort_key desc, listed.name
+limit $4 offset $5
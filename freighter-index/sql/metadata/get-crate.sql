@@ -0,0 +1,19 @@
+select crates.name,
+       crates.description,
+       crates.documentation,
+       crates.homepage,
+       crates.repository,
+       crates.downloads,
+       crates.created_at,
+       crates.updated_at,
+       coalesce((select array_agg(c.name order by c.name)
+                 from crate_categories cc
+                          join categories c on c.id = cc.category
+                 where cc.crate = crates.id), '{}') as categories,
+       coalesce((select array_agg(k.name order by k.name)
+                 from crate_keywords ck
+                          join keywords k on k.id = ck.keyword
+                 where ck.crate = crates.id), '{}') as keywords
+from crates
+where crates.name = $1
+  and crates.registry is null
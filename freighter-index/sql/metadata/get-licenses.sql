@@ -0,0 +1,4 @@
+select crates.name, cv.version, cv.license
+from unnest($1::text[], $2::text[]) as u(name, version)
+         join crates on crates.name = u.name and crates.registry is null
+         join crate_versions cv on cv.crate = crates.id and cv.version = u.version
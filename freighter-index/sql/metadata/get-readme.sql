@@ -0,0 +1,7 @@
+select cv.readme, cv.readme_file
+from crates
+         join crate_versions cv on crates.id = cv.crate
+where crates.name = $1
+  and crates.registry is null
+  and cv.version = $2
+  and cv.readme is not null
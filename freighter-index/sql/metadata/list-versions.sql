@@ -0,0 +1,24 @@
+select crates.name,
+       cv.id,
+       cv.version,
+       cv.cksum,
+       cv.yanked,
+       cv.links,
+       cv.rust_version,
+       cv.description,
+       cv.license,
+       cv.license_file,
+       cv.authors,
+       cv.published_by,
+       cv.downloads,
+       cv.created_at,
+       cv.updated_at,
+       coalesce((select jsonb_object_agg(f.name, f.values)
+                 from features f
+                 where f.crate_version = cv.id), '{}') as features
+from crates
+         join crate_versions cv on crates.id = cv.crate
+where crates.name = $1
+  and crates.registry is null
+  and ($2::text is null or cv.version = $2)
+order by cv.id desc
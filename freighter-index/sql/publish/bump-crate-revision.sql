@@ -0,0 +1,5 @@
+update crates
+set revision    = nextval('crate_revisions'),
+    changed_xid = pg_current_xact_id(),
+    updated_at  = now()
+where id = $1
@@ -0,0 +1 @@
+select exists(select 1 from crate_versions where crate = $1)
@@ -1,3 +1,3 @@
-insert into features (crate_version, name, values)
-values ($1, $2, $3)
+insert into features (crate_version, name, values, extended)
+values ($1, $2, $3, $4)
 returning id
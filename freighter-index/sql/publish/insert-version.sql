@@ -1,3 +1,4 @@
-insert into crate_versions (crate, version, cksum, yanked, links)
-values ($1, $2, $3, $4, $5)
+insert into crate_versions (crate, version, cksum, yanked, links, rust_version, description, license, license_file,
+                            authors, readme, readme_file, published_by)
+values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
 returning id
@@ -0,0 +1,3 @@
+update crates
+set mirrored_from = $2
+where id = $1
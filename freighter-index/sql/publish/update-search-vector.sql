@@ -0,0 +1,14 @@
+-- names are weighted highest, followed by keywords, categories, and finally descriptions
+update crates
+set search_vector =
+            setweight(to_tsvector('english', name), 'A') ||
+            setweight(to_tsvector('english', coalesce((select string_agg(k.name, ' ')
+                                                       from crate_keywords ck
+                                                                join keywords k on k.id = ck.keyword
+                                                       where ck.crate = crates.id), '')), 'B') ||
+            setweight(to_tsvector('english', coalesce((select string_agg(c.name, ' ')
+                                                       from crate_categories cc
+                                                                join categories c on c.id = cc.category
+                                                       where cc.crate = crates.id), '')), 'C') ||
+            setweight(to_tsvector('english', coalesce(description, '')), 'D')
+where id = $1
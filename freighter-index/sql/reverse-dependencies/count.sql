@@ -0,0 +1,17 @@
+-- the most recently published version which isn't yanked of each crate with any version depending
+-- on the crate
+with latest as (select distinct on (cv.crate) cv.id, cv.crate
+                from crate_versions cv
+                where not cv.yanked
+                  and cv.crate in (select dependent.crate
+                                   from dependencies d
+                                            join crate_versions dependent on dependent.id = d.dependent
+                                   where d.dependency = (select id from crates where name = $1 and registry is null))
+                order by cv.crate, cv.id desc)
+select (select count(*)
+        from dependencies d
+                 join latest on latest.id = d.dependent
+        where d.dependency = crates.id) as total
+from crates
+where crates.name = $1
+  and crates.registry is null
@@ -0,0 +1,25 @@
+-- the most recently published version which isn't yanked of each crate with any version depending
+-- on the crate
+with latest as (select distinct on (cv.crate) cv.id, cv.crate, cv.version
+                from crate_versions cv
+                where not cv.yanked
+                  and cv.crate in (select dependent.crate
+                                   from dependencies d
+                                            join crate_versions dependent on dependent.id = d.dependent
+                                   where d.dependency = (select id from crates where name = $1 and registry is null))
+                order by cv.crate, cv.id desc)
+select crates.name,
+       latest.version,
+       d.req,
+       d.features,
+       d.optional,
+       d.default_features,
+       d.target,
+       d.kind,
+       crates.downloads
+from dependencies d
+         join latest on latest.id = d.dependent
+         join crates on crates.id = latest.crate
+where d.dependency = (select id from crates where name = $1 and registry is null)
+order by crates.downloads desc, crates.name, d.id
+limit $2 offset $3
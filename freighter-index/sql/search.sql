@@ -1,20 +1,43 @@
+with query as (select websearch_to_tsquery('english', $1) as tsquery)
 select crates.name,
        crates.description,
        crates.documentation,
        crates.homepage,
        crates.repository,
-       array_agg(cv.version)      as versions,
-       count(dependency),
-       array_agg(distinct c.name) as categories,
-       array_agg(distinct k.name) as keywords
-from crates
-         join crate_versions cv on crates.id = cv.crate
-         left join dependencies d on crates.id = d.dependency
-         left join crate_categories cc on crates.id = cc.crate
-         join categories c on c.id = cc.category
-         left join crate_keywords ck on crates.id = ck.crate
-         join keywords k on k.id = ck.keyword
+       (select array_agg(cv.version)
+        from crate_versions cv
+        where cv.crate = crates.id)                   as versions,
+       coalesce((select array_agg(c.name order by c.name)
+                 from crate_categories cc
+                          join categories c on c.id = cc.category
+                 where cc.crate = crates.id), '{}') as categories,
+       coalesce((select array_agg(k.name order by k.name)
+                 from crate_keywords ck
+                          join keywords k on k.id = ck.keyword
+                 where ck.crate = crates.id), '{}') as keywords,
+       count(*) over ()                                as total
+from crates,
+     query
 where crates.registry is null
-  and position($1 in crates.name) > 0
-group by crates.name, crates.description, crates.documentation, crates.homepage, crates.repository
-having count(cv.version) > 0
+  and exists(select 1 from crate_versions cv where cv.crate = crates.id)
+  -- substring matches on the name keep partially typed names working
+  and ($1 = ''
+    or crates.search_vector @@ query.tsquery
+    or strpos(lower(crates.name), lower($1)) > 0)
+  and ($2::text is null
+    or exists(select 1
+              from crate_keywords ck
+                       join keywords k on k.id = ck.keyword
+              where ck.crate = crates.id
+                and k.name = $2))
+  and ($3::text is null
+    or exists(select 1
+              from crate_categories cc
+                       join categories c on c.id = cc.category
+              where cc.crate = crates.id
+                and c.name = $3))
+  and ($4::text[] is null or crates.name = any ($4))
+order by lower(crates.name) = lower($1) desc,
+         ts_rank(crates.search_vector, query.tsquery) desc,
+         crates.name
+limit $5
@@ -1,7 +1,17 @@
-update crate_versions cv
-set yanked = $3
-from crates c
-where c.name = $1
-  and cv.crate = c.id
-  and cv.version = $2
-returning c.name, cv.version, cv.yanked;
+with updated as (
+    update crate_versions cv
+        set yanked     = $3,
+            updated_at = now()
+        from crates c
+        where c.name = $1
+            and cv.crate = c.id
+            and cv.version = $2
+        returning c.id, c.name, cv.version, cv.yanked),
+     bumped as (
+         update crates
+             set revision = nextval('crate_revisions'),
+                 changed_xid = pg_current_xact_id(),
+                 updated_at = now()
+             where id in (select id from updated))
+select id, name, version, yanked
+from updated;
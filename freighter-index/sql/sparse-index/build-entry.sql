@@ -0,0 +1,29 @@
+select cv.version,
+       cv.cksum,
+       cv.yanked,
+       cv.links,
+       cv.rust_version,
+       coalesce((select json_object_agg(f.name, f.values)
+                 from features f
+                 where f.crate_version = cv.id
+                   and not f.extended), '{}') as features,
+       coalesce((select json_object_agg(f.name, f.values)
+                 from features f
+                 where f.crate_version = cv.id
+                   and f.extended), '{}') as features2,
+       coalesce((select json_agg(json_build_object(
+                                         'name', c.name,
+                                         'req', d.req,
+                                         'features', d.features,
+                                         'optional', d.optional,
+                                         'default_features', d.default_features,
+                                         'target', d.target,
+                                         'kind', d.kind,
+                                         'registry', c.registry,
+                                         'package', d.package
+                                     ) order by d.id)
+                 from dependencies d
+                          join crates c on c.id = d.dependency
+                 where d.dependent = cv.id), '[]') as deps
+from crate_versions cv
+where cv.crate = $1
@@ -1,3 +0,0 @@
-select id
-from crates
-where name = $1
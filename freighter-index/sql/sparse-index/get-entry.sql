@@ -0,0 +1,4 @@
+select se.entry, c.revision, c.updated_at
+from sparse_entries se
+         join crates c on c.id = se.crate
+where se.name = $1
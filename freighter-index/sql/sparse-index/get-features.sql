@@ -1,3 +0,0 @@
-select *
-from features
-where crate_version = $1
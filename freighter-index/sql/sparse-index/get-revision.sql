@@ -0,0 +1,4 @@
+select revision, updated_at
+from crates
+where name = $1
+  and registry is null
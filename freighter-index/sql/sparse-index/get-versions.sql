@@ -1,3 +0,0 @@
-select *
-from crate_versions
-where crate = $1
@@ -0,0 +1,6 @@
+select c.name, c.revision, c.updated_at
+from crates c
+         join sparse_entries se on se.crate = c.id
+where $1::bigint is null
+   or c.changed_xid >= $1::bigint::text::xid8
+order by c.name
@@ -0,0 +1,5 @@
+select c.id, c.name
+from crates c
+where c.registry is null
+  and exists(select 1 from crate_versions cv where cv.crate = c.id)
+order by c.id
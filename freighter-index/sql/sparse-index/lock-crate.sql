@@ -0,0 +1,4 @@
+select id
+from crates
+where id = $1
+    for update
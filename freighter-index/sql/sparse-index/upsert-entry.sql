@@ -0,0 +1,4 @@
+insert into sparse_entries (name, crate, entry)
+values ($1, $2, $3)
+on conflict (name) do update set entry = excluded.entry
+where sparse_entries.entry is distinct from excluded.entry
@@ -1,8 +1,11 @@
+use bytes::Bytes;
 use semver::{Version, VersionReq};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+use time::{Date, OffsetDateTime};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[cfg_attr(
     feature = "postgresql-backend",
     derive(postgres_types::ToSql, postgres_types::FromSql)
@@ -19,7 +22,7 @@ pub enum DependencyKind {
     Build,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CrateVersion {
     /// The name of the package.
     ///
@@ -37,6 +40,7 @@ pub struct CrateVersion {
     /// Set of features defined for the package.
     ///
     /// Each feature maps to an array of features or dependencies it enables.
+    #[serde(serialize_with = "serialize_sorted")]
     pub features: HashMap<String, Vec<String>>,
     /// Boolean of whether or not this version has been yanked.
     pub yanked: bool,
@@ -56,9 +60,10 @@ pub struct CrateVersion {
     /// The current values are:
     ///
     /// * 1: The schema as documented here, not including newer additions.
-    ///      This is honored in Rust version 1.51 and newer.
+    ///   This is honored in Rust version 1.51 and newer.
     /// * 2: The addition of the `features2` field.
-    ///      This is honored in Rust version 1.60 and newer.
+    ///   This is honored in Rust version 1.60 and newer.
+    #[serde(default = "default_schema_version")]
     pub v: u32,
     /// This optional field contains features with new, extended syntax.
     ///
@@ -75,10 +80,83 @@ pub struct CrateVersion {
     /// to include those in the "features" field. Using this is only necessary if the registry
     /// wants to support cargo versions older than 1.19, which in practice is only crates.io since
     /// those older versions do not support other registries.
+    #[serde(default, serialize_with = "serialize_sorted")]
     pub features2: HashMap<String, Vec<String>>,
+    /// The minimum supported Rust version of the package, from its manifest's `rust-version`.
+    ///
+    /// This field is optional, and is used by cargo to prefer compatible versions when resolving.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
 }
 
-#[derive(Serialize)]
+fn default_schema_version() -> u32 {
+    1
+}
+
+/// Serialize a map in order of its keys, so that rendering an entry twice gives the same result.
+fn serialize_sorted<S>(map: &HashMap<String, Vec<String>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Information identifying a particular state of a crate's sparse index entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SparseEntryRevision {
+    /// A number which changes whenever the entry changes.
+    ///
+    /// Revisions may be assigned before a change becomes visible, so they don't reflect the order
+    /// in which changes were made.
+    /// Use [`IndexProvider::list_sparse_entry_changes`](crate::IndexProvider) to find changes.
+    pub revision: u64,
+    /// The time at which the entry last changed.
+    pub last_modified: SystemTime,
+}
+
+/// The revision of a particular crate's sparse index entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrateRevision {
+    pub name: String,
+    pub revision: SparseEntryRevision,
+}
+
+/// Crates whose sparse entries changed, as listed by
+/// [`IndexProvider::list_sparse_entry_changes`](crate::IndexProvider).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SparseEntryChanges {
+    /// The crates which changed, along with the current revisions of their entries.
+    pub crates: Vec<CrateRevision>,
+    /// Value to pass as `since` to list the changes made after this listing.
+    pub watermark: u64,
+}
+
+/// A crate's sparse index entry, rendered in the format served to cargo.
+#[derive(Clone, Debug)]
+pub struct RenderedSparseEntry {
+    /// The entry, as JSON lines.
+    pub contents: Bytes,
+    /// The revision of the entry, if the index tracks revisions.
+    pub revision: Option<SparseEntryRevision>,
+}
+
+/// The `config.json` file at the root of a sparse index.
+#[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    /// The URL of the crate download endpoint.
+    pub dl: String,
+    /// The URL of the registry web API.
+    pub api: String,
+}
+
+/// Notification that a crate in the index has changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexChange {
+    /// The name of the crate which was published to, yanked, or unyanked.
+    pub crate_name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Dependency {
     /// Name of the dependency.
     ///
@@ -105,6 +183,7 @@ pub struct Dependency {
     /// "dev", "build", or "normal".
     /// Note: this is a required field, but a small number of entries exist in the crates.io index
     /// with either a missing or null kind` field due to implementation bugs.
+    #[serde(default)]
     pub kind: DependencyKind,
     /// The URL of the index of the registry where this dependency is from as a string.
     ///
@@ -129,15 +208,139 @@ pub struct SearchQuery {
     pub q: String,
     /// Number of results, default 10, max 100.
     pub per_page: Option<usize>,
+    /// Only include crates with this keyword.
+    pub keyword: Option<String>,
+    /// Only include crates in this category.
+    pub category: Option<String>,
+    /// Only include crates owned by the user with this login.
+    pub owner: Option<String>,
+}
+
+/// Restrictions on the crates returned by [`IndexProvider::search`](crate::IndexProvider::search).
+#[derive(Clone, Debug, Default)]
+pub struct SearchFilters {
+    /// Only include crates with this keyword.
+    pub keyword: Option<String>,
+    /// Only include crates in this category.
+    pub category: Option<String>,
+    /// Only include crates with one of these names.
+    pub crates: Option<Vec<String>>,
 }
 
 /// Pagination information for certain operations.
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Default, Deserialize)]
 pub struct ListQuery {
     /// The number of crates to show in a given page.
     pub per_page: Option<usize>,
     /// The page to show.
+    ///
+    /// Ignored if `seek` is set, which should be preferred as it is cheaper for large listings.
     pub page: Option<usize>,
+    /// The order to list crates in, by name if unset.
+    pub sort: Option<ListSort>,
+    /// Show the page following the crate this was taken from, as given in the `next_page` of the
+    /// previous page.
+    pub seek: Option<ListSeek>,
+}
+
+impl ListQuery {
+    /// The query string of the page following one ending at `last`.
+    pub fn next_page(&self, last: &ListSeek) -> String {
+        self.page_query(format!("seek={last}"))
+    }
+
+    /// The query string of the page preceding this one, if it was requested by page number.
+    pub fn prev_page(&self) -> Option<String> {
+        let page = self.page.filter(|&page| page > 0 && self.seek.is_none())?;
+
+        Some(self.page_query(format!("page={}", page - 1)))
+    }
+
+    /// The number of crates preceding the requested page, or `None` if it is too large to
+    /// represent.
+    pub fn offset(&self) -> Option<i64> {
+        match (&self.seek, self.per_page, self.page) {
+            (None, Some(per_page), Some(page)) => per_page
+                .checked_mul(page)
+                .and_then(|offset| i64::try_from(offset).ok()),
+            _ => Some(0),
+        }
+    }
+
+    fn page_query(&self, position: String) -> String {
+        let mut query = format!("?{position}");
+
+        if let Some(per_page) = self.per_page {
+            query.push_str(&format!("&per_page={per_page}"));
+        }
+
+        if let Some(sort) = self.sort {
+            query.push_str(&format!("&sort={}", sort.as_str()));
+        }
+
+        query
+    }
+}
+
+/// Orders in which crates can be listed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListSort {
+    /// Alphabetically by name.
+    #[default]
+    Alpha,
+    /// Most recently updated first.
+    RecentUpdates,
+    /// Most downloaded first.
+    Downloads,
+    /// Most depended upon by other crates first.
+    ReverseDependencies,
+}
+
+impl ListSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListSort::Alpha => "alpha",
+            ListSort::RecentUpdates => "recent-updates",
+            ListSort::Downloads => "downloads",
+            ListSort::ReverseDependencies => "reverse-dependencies",
+        }
+    }
+}
+
+/// Position in a listing, for keyset pagination.
+///
+/// This is the sort key and name of the last crate on a page, and is rendered as
+/// `{key}.{name}`, which is safe to use in a query string as-is since crate names can't contain
+/// a `.`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ListSeek {
+    pub key: i64,
+    pub name: String,
+}
+
+impl std::fmt::Display for ListSeek {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.key, self.name)
+    }
+}
+
+impl TryFrom<String> for ListSeek {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (key, name) = value
+            .split_once('.')
+            .ok_or_else(|| format!("Invalid seek {value}"))?;
+
+        let key = key.parse().map_err(|_| format!("Invalid seek {value}"))?;
+
+        Ok(Self {
+            key,
+            name: name.to_string(),
+        })
+    }
 }
 
 #[derive(Serialize)]
@@ -151,6 +354,10 @@ pub struct SearchResults {
 pub struct SearchResultsMeta {
     /// Total number of results available on the server.
     pub total: usize,
+    /// Query string of the next page of results, if there is one.
+    pub next_page: Option<String>,
+    /// Query string of the previous page of results, if there is one.
+    pub prev_page: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -168,6 +375,316 @@ pub struct SearchResultsEntry {
     pub categories: Vec<String>,
 }
 
+/// Information about a crate, in the shape of the crates.io API.
+#[derive(Clone, Debug, Serialize)]
+pub struct CrateMetadata {
+    /// Identifier of the crate, which is the same as its name.
+    pub id: String,
+    /// Name of the crate.
+    pub name: String,
+    /// Textual description of the crate, as of the most recent publish.
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub documentation: Option<String>,
+    pub repository: Option<String>,
+    /// The highest version which isn't yanked, or the highest version if all are yanked.
+    pub max_version: Version,
+    /// The highest version which isn't yanked or a pre-release, if any.
+    pub max_stable_version: Option<Version>,
+    /// The most recently published version.
+    pub newest_version: Version,
+    /// Total number of downloads across all versions.
+    pub downloads: u64,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// Identifiers of the crate's versions, newest first.
+    pub versions: Vec<u32>,
+    /// Time at which the crate was first published.
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Time at which a version of the crate was last published, yanked, or unyanked.
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+/// Information about a particular version of a crate, in the shape of the crates.io API.
+#[derive(Clone, Debug, Serialize)]
+pub struct VersionMetadata {
+    /// Identifier of the version, unique across all crates.
+    pub id: u32,
+    /// Name of the crate.
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    /// The version number.
+    pub num: Version,
+    /// A SHA256 checksum of the `.crate` file.
+    pub checksum: String,
+    pub yanked: bool,
+    /// Set of features defined for the version.
+    pub features: HashMap<String, Vec<String>>,
+    /// Description of the crate as of this version.
+    pub description: Option<String>,
+    /// The SPDX license expression of the version.
+    pub license: Option<String>,
+    /// Path of the license file in the crate, for versions without a license expression.
+    pub license_file: Option<String>,
+    pub authors: Vec<String>,
+    /// The user who published the version, if known.
+    pub published_by: Option<VersionPublisher>,
+    /// The `links` value from the version's manifest.
+    pub lib_links: Option<String>,
+    /// The minimum supported Rust version of the version.
+    pub rust_version: Option<String>,
+    /// Number of times this version has been downloaded.
+    pub downloads: u64,
+    /// Time at which the version was published.
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Time at which the version was published, yanked, or unyanked.
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+/// A user who published a version.
+#[derive(Clone, Debug, Serialize)]
+pub struct VersionPublisher {
+    pub login: String,
+}
+
+/// Response of the crate metadata endpoint.
+#[derive(Serialize)]
+pub struct CrateResponse {
+    #[serde(rename = "crate")]
+    pub crate_metadata: CrateMetadata,
+    pub versions: Vec<VersionMetadata>,
+}
+
+/// Response of the crate versions endpoint.
+#[derive(Serialize)]
+pub struct VersionsResponse {
+    pub versions: Vec<VersionMetadata>,
+    pub meta: VersionsMeta,
+}
+
+#[derive(Serialize)]
+pub struct VersionsMeta {
+    /// Total number of versions of the crate.
+    pub total: usize,
+}
+
+/// Response of the version metadata endpoint.
+#[derive(Serialize)]
+pub struct VersionResponse {
+    pub version: VersionMetadata,
+}
+
+/// The README of a crate version, as extracted from its `.crate` file at publish time.
+#[derive(Clone, Debug)]
+pub struct VersionReadme {
+    /// The contents of the README.
+    pub contents: String,
+    /// The path of the README within the package, if known.
+    pub file: Option<String>,
+}
+
+/// A file in a published `.crate`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CrateFile {
+    /// Path of the file, relative to the root of the package.
+    pub path: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Unix permission bits of the file.
+    pub mode: u32,
+}
+
+/// Response of the crate file listing endpoint.
+#[derive(Serialize)]
+pub struct CrateFilesResponse {
+    pub files: Vec<CrateFile>,
+}
+
+/// Differences between the files of two versions of a crate.
+#[derive(Serialize)]
+pub struct CrateDiff {
+    pub from: Version,
+    pub to: Version,
+    /// Summaries of the files which differ, sorted by path.
+    pub files: Vec<FileDiff>,
+    /// Unified diff of the text files which differ.
+    pub diff: String,
+}
+
+/// Summary of the changes to a file between two versions of a crate.
+#[derive(Serialize)]
+pub struct FileDiff {
+    /// Path of the file, relative to the root of the package.
+    pub path: String,
+    pub status: FileDiffStatus,
+    /// Whether either side of the file is binary, in which case it isn't included in the diff.
+    pub binary: bool,
+    /// Whether either side of the file is too large to be diffed, in which case it isn't included
+    /// in the diff.
+    pub too_large: bool,
+    /// Number of lines added.
+    pub additions: usize,
+    /// Number of lines removed.
+    pub deletions: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileDiffStatus {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Response of the docs upload endpoint.
+#[derive(Serialize)]
+pub struct DocsUploadResponse {
+    /// Number of files stored.
+    pub files: usize,
+}
+
+/// Pagination of the reverse dependencies of a crate.
+#[derive(Clone, Default, Deserialize)]
+pub struct ReverseDependenciesQuery {
+    /// The number of dependencies to show in a given page.
+    pub per_page: Option<usize>,
+    /// The page to show, starting from 0.
+    pub page: Option<usize>,
+}
+
+impl ReverseDependenciesQuery {
+    /// The number of dependencies preceding the requested page, or `None` if it is too large to
+    /// represent.
+    pub fn offset(&self) -> Option<i64> {
+        match (self.per_page, self.page) {
+            (Some(per_page), Some(page)) => per_page
+                .checked_mul(page)
+                .and_then(|offset| i64::try_from(offset).ok()),
+            _ => Some(0),
+        }
+    }
+}
+
+/// A dependency on a crate from another crate.
+///
+/// Only the most recently published version of the dependent crate which isn't yanked is
+/// considered.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReverseDependency {
+    /// Name of the dependent crate.
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    /// The version of the dependent crate with this dependency.
+    pub version: Version,
+    pub req: VersionReq,
+    pub features: Vec<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub target: Option<String>,
+    pub kind: DependencyKind,
+    /// Total number of downloads of the dependent crate.
+    pub downloads: u64,
+}
+
+/// A page of the reverse dependencies of a crate, ordered by the downloads of the dependent
+/// crates.
+#[derive(Serialize)]
+pub struct ReverseDependencies {
+    pub dependencies: Vec<ReverseDependency>,
+    pub meta: ReverseDependenciesMeta,
+}
+
+#[derive(Serialize)]
+pub struct ReverseDependenciesMeta {
+    /// Total number of reverse dependencies across all pages.
+    pub total: usize,
+}
+
+/// A particular version of a crate.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct PackageId {
+    pub name: String,
+    pub version: Version,
+}
+
+/// The transitive dependencies of a crate version, with a particular set of features enabled.
+#[derive(Serialize)]
+pub struct DependencyGraph {
+    /// The crate version the graph was resolved for.
+    pub root: PackageId,
+    /// Every package in the graph, including the root, sorted by name and version.
+    pub packages: Vec<GraphPackage>,
+}
+
+/// A package in a [`DependencyGraph`].
+#[derive(Serialize)]
+pub struct GraphPackage {
+    pub name: String,
+    pub version: Version,
+    /// SHA-256 checksum of the `.crate` file.
+    pub checksum: String,
+    /// SPDX license expression of the package, if it has one.
+    pub license: Option<String>,
+    /// The features enabled on the package.
+    pub features: Vec<String>,
+    /// The packages this package depends on in the graph.
+    pub dependencies: Vec<GraphDependency>,
+    /// Dependencies on crates from other registries, which aren't resolved.
+    pub unresolved: Vec<UnresolvedDependency>,
+}
+
+/// An edge of a [`DependencyGraph`].
+#[derive(Serialize)]
+pub struct GraphDependency {
+    pub name: String,
+    /// The version the dependency was resolved to.
+    pub version: Version,
+    pub req: VersionReq,
+    pub kind: DependencyKind,
+    pub target: Option<String>,
+}
+
+/// A dependency on a crate from another registry, which can't be resolved from the index.
+#[derive(Clone, Serialize)]
+pub struct UnresolvedDependency {
+    /// Name of the crate in the other registry.
+    pub name: String,
+    pub req: VersionReq,
+    /// The URL of the index of the registry the crate is from.
+    pub registry: String,
+    pub kind: DependencyKind,
+    pub target: Option<String>,
+}
+
+/// Downloads of a crate version which have yet to be recorded in the index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadCount {
+    pub crate_name: String,
+    pub version: Version,
+    pub downloads: u64,
+}
+
+/// The number of times a version was downloaded on a particular day.
+#[derive(Clone, Debug, Serialize)]
+pub struct VersionDownloads {
+    /// Identifier of the version, as in [`VersionMetadata::id`].
+    pub version: u32,
+    /// Number of downloads on the day.
+    pub downloads: u64,
+    /// The day, in UTC.
+    pub date: Date,
+}
+
+/// Response of the crate downloads endpoint.
+#[derive(Serialize)]
+pub struct DownloadsResponse {
+    pub version_downloads: Vec<VersionDownloads>,
+}
+
 #[derive(Deserialize)]
 pub struct Publish {
     /// The name of the package.
@@ -234,6 +751,11 @@ pub struct Publish {
     ///
     /// This field is optional and defaults to null.
     pub links: Option<String>,
+    /// The minimum supported Rust version of the package, from its manifest's `rust-version`.
+    ///
+    /// May be null.
+    #[serde(default)]
+    pub rust_version: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,382 @@
+//! Index provider wrapper which caches sparse index entries in memory.
+//!
+//! The sparse index is by far the hottest path in the registry, and in bursty workloads such as
+//! CI builds the same handful of entries tend to be requested over and over.
+//! [`CachingIndexProvider`] keeps recently requested entries in an LRU cache bounded by an
+//! approximate size in bytes, and drops a crate's entry whenever that crate is published to,
+//! yanked, or unyanked through it.
+//! Entries are cached as rendered for cargo along with their revision, so cache hits answer both
+//! [`IndexProvider::get_rendered_sparse_entry`] and [`IndexProvider::get_sparse_entry_revision`]
+//! without going to the inner provider.
+//!
+//! If several instances of freighter share the same backing index, changes made through one
+//! instance will not be seen by the caches of the others until they are evicted.
+//! To keep them coherent, feed a stream of [`IndexChange`]s, such as the one provided by
+//! `PgIndexProvider::subscribe`, to [`CacheInvalidator::follow`].
+//!
+//! # Metrics
+//! * `sparse_entry_cache_lookups_total`: counter of cache lookups, labeled by `result` (`hit` or
+//!   `miss`).
+//! * `sparse_entry_cache_size_bytes`: gauge of the approximate size of the cached entries.
+//! * `sparse_entry_cache_entries`: gauge of the number of cached entries.
+
+use crate::{
+    parse_sparse_entry, CompletedPublication, CrateResponse, CrateVersion, DownloadCount,
+    IndexChange, IndexProvider, IndexResult, ListQuery, PackageId, Publish, RenderedSparseEntry,
+    ReverseDependencies, ReverseDependenciesQuery, SearchFilters, SearchResults,
+    SparseEntryChanges, SparseEntryRevision, VersionDownloads, VersionMetadata, VersionReadme,
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use futures_util::{Stream, StreamExt};
+use lru::LruCache;
+use metrics::{gauge, increment_counter};
+use semver::Version;
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem::size_of;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use time::Date;
+
+/// Index provider which caches the sparse entries of an inner provider.
+///
+/// See [the module-level docs](super::caching) for more information.
+pub struct CachingIndexProvider<I> {
+    inner: I,
+    cache: Arc<Mutex<EntryCache>>,
+}
+
+/// Handle for invalidating the entries of a [`CachingIndexProvider`] from elsewhere.
+#[derive(Clone)]
+pub struct CacheInvalidator {
+    cache: Arc<Mutex<EntryCache>>,
+}
+
+struct EntryCache {
+    entries: LruCache<String, CachedEntry>,
+    size: usize,
+    max_size: usize,
+    /// Incremented on every invalidation, so that lookups which raced with an invalidation don't
+    /// insert stale entries.
+    generation: u64,
+}
+
+struct CachedEntry {
+    entry: RenderedSparseEntry,
+    size: usize,
+}
+
+impl<I> CachingIndexProvider<I> {
+    /// Wrap an index provider, caching up to approximately `max_size` bytes of sparse entries.
+    pub fn new(inner: I, max_size: usize) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(EntryCache {
+                entries: LruCache::unbounded(),
+                size: 0,
+                max_size,
+                generation: 0,
+            })),
+        }
+    }
+
+    /// Get a handle which can be used to invalidate entries in this cache.
+    pub fn invalidator(&self) -> CacheInvalidator {
+        CacheInvalidator {
+            cache: self.cache.clone(),
+        }
+    }
+
+    /// Drop the cached entry for a crate, if any.
+    pub fn invalidate(&self, crate_name: &str) {
+        self.cache.lock().unwrap().invalidate(crate_name);
+    }
+
+    /// Drop every cached entry.
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().invalidate_all();
+    }
+}
+
+impl CacheInvalidator {
+    /// Drop the cached entry for a crate, if any.
+    pub fn invalidate(&self, crate_name: &str) {
+        self.cache.lock().unwrap().invalidate(crate_name);
+    }
+
+    /// Drop every cached entry.
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().invalidate_all();
+    }
+
+    /// Invalidate entries as changes arrive on a stream, until the stream ends or yields an error.
+    ///
+    /// Everything is invalidated when starting and when the stream ends, since any changes made
+    /// while not following a stream would otherwise be missed.
+    pub async fn follow<S>(&self, changes: S) -> IndexResult<()>
+    where
+        S: Stream<Item = IndexResult<IndexChange>>,
+    {
+        self.invalidate_all();
+
+        futures_util::pin_mut!(changes);
+
+        let res = loop {
+            match changes.next().await {
+                Some(Ok(change)) => self.invalidate(&change.crate_name),
+                Some(Err(error)) => break Err(error),
+                None => break Ok(()),
+            }
+        };
+
+        self.invalidate_all();
+
+        res
+    }
+}
+
+impl EntryCache {
+    fn invalidate(&mut self, crate_name: &str) {
+        self.generation += 1;
+
+        if let Some(entry) = self.entries.pop(crate_name) {
+            self.size -= entry.size;
+        }
+
+        self.record_gauges();
+    }
+
+    fn invalidate_all(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+        self.size = 0;
+
+        self.record_gauges();
+    }
+
+    fn get(&mut self, crate_name: &str) -> Option<RenderedSparseEntry> {
+        self.entries
+            .get(crate_name)
+            .map(|cached| cached.entry.clone())
+    }
+
+    fn insert(&mut self, crate_name: &str, entry: RenderedSparseEntry) {
+        let size = size_of::<CachedEntry>() + crate_name.len() + entry.contents.len();
+
+        // don't let a single huge entry flush out the entire cache
+        if size > self.max_size {
+            return;
+        }
+
+        if let Some(old) = self
+            .entries
+            .put(crate_name.to_string(), CachedEntry { entry, size })
+        {
+            self.size -= old.size;
+        }
+
+        self.size += size;
+
+        while self.size > self.max_size {
+            match self.entries.pop_lru() {
+                Some((_, evicted)) => self.size -= evicted.size,
+                None => break,
+            }
+        }
+
+        self.record_gauges();
+    }
+
+    fn record_gauges(&self) {
+        gauge!("sparse_entry_cache_size_bytes", self.size as f64);
+        gauge!("sparse_entry_cache_entries", self.entries.len() as f64);
+    }
+}
+
+#[async_trait]
+impl<I> IndexProvider for CachingIndexProvider<I>
+where
+    I: IndexProvider + Send,
+{
+    async fn get_sparse_entry(&self, crate_name: &str) -> IndexResult<Vec<CrateVersion>> {
+        let entry = self.get_rendered_sparse_entry(crate_name).await?;
+
+        let contents =
+            std::str::from_utf8(&entry.contents).context("Cached sparse entry is not UTF-8")?;
+
+        let versions =
+            parse_sparse_entry(contents).context("Failed to parse cached sparse entry")?;
+
+        Ok(versions)
+    }
+
+    async fn get_sparse_entry_revision(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<Option<SparseEntryRevision>> {
+        let cached = self.cache.lock().unwrap().get(crate_name);
+
+        if let Some(entry) = cached {
+            increment_counter!("sparse_entry_cache_lookups_total", "result" => "hit");
+
+            return Ok(entry.revision);
+        }
+
+        increment_counter!("sparse_entry_cache_lookups_total", "result" => "miss");
+
+        self.inner.get_sparse_entry_revision(crate_name).await
+    }
+
+    async fn get_rendered_sparse_entry(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<RenderedSparseEntry> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+
+            if let Some(entry) = cache.get(crate_name) {
+                increment_counter!("sparse_entry_cache_lookups_total", "result" => "hit");
+
+                return Ok(entry);
+            }
+
+            cache.generation
+        };
+
+        increment_counter!("sparse_entry_cache_lookups_total", "result" => "miss");
+
+        let entry = self.inner.get_rendered_sparse_entry(crate_name).await?;
+
+        let mut cache = self.cache.lock().unwrap();
+
+        if cache.generation == generation {
+            cache.insert(crate_name, entry.clone());
+        }
+
+        Ok(entry)
+    }
+
+    async fn list_sparse_entry_changes(
+        &self,
+        since: Option<u64>,
+    ) -> IndexResult<SparseEntryChanges> {
+        self.inner.list_sparse_entry_changes(since).await
+    }
+
+    async fn confirm_existence(&self, crate_name: &str, version: &Version) -> IndexResult<bool> {
+        self.inner.confirm_existence(crate_name, version).await
+    }
+
+    async fn yank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
+        let res = self.inner.yank_crate(crate_name, version).await;
+
+        self.invalidate(crate_name);
+
+        res
+    }
+
+    async fn unyank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
+        let res = self.inner.unyank_crate(crate_name, version).await;
+
+        self.invalidate(crate_name);
+
+        res
+    }
+
+    async fn search(
+        &self,
+        query_string: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> IndexResult<SearchResults> {
+        self.inner.search(query_string, filters, limit).await
+    }
+
+    async fn publish(
+        &self,
+        version: &Publish,
+        checksum: &str,
+        publisher: Option<&str>,
+        end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+    ) -> IndexResult<CompletedPublication> {
+        let res = self
+            .inner
+            .publish(version, checksum, publisher, end_step)
+            .await;
+
+        self.invalidate(&version.name);
+
+        res
+    }
+
+    async fn publish_mirrored(
+        &self,
+        version: &Publish,
+        checksum: &str,
+        source: &str,
+        end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+    ) -> IndexResult<CompletedPublication> {
+        let res = self
+            .inner
+            .publish_mirrored(version, checksum, source, end_step)
+            .await;
+
+        self.invalidate(&version.name);
+
+        res
+    }
+
+    async fn get_crate(&self, crate_name: &str) -> IndexResult<CrateResponse> {
+        self.inner.get_crate(crate_name).await
+    }
+
+    async fn list_versions(&self, crate_name: &str) -> IndexResult<Vec<VersionMetadata>> {
+        self.inner.list_versions(crate_name).await
+    }
+
+    async fn get_version_metadata(
+        &self,
+        crate_name: &str,
+        version: &Version,
+    ) -> IndexResult<VersionMetadata> {
+        self.inner.get_version_metadata(crate_name, version).await
+    }
+
+    async fn get_licenses(
+        &self,
+        packages: &[PackageId],
+    ) -> IndexResult<HashMap<PackageId, Option<String>>> {
+        self.inner.get_licenses(packages).await
+    }
+
+    async fn get_readme(&self, crate_name: &str, version: &Version) -> IndexResult<VersionReadme> {
+        self.inner.get_readme(crate_name, version).await
+    }
+
+    async fn record_downloads(&self, downloads: &[DownloadCount]) -> IndexResult<()> {
+        self.inner.record_downloads(downloads).await
+    }
+
+    async fn get_version_downloads(
+        &self,
+        crate_name: &str,
+        since: Date,
+    ) -> IndexResult<Vec<VersionDownloads>> {
+        self.inner.get_version_downloads(crate_name, since).await
+    }
+
+    async fn list_reverse_dependencies(
+        &self,
+        crate_name: &str,
+        pagination: &ReverseDependenciesQuery,
+    ) -> IndexResult<ReverseDependencies> {
+        self.inner
+            .list_reverse_dependencies(crate_name, pagination)
+            .await
+    }
+
+    async fn list(&self, pagination: &ListQuery) -> IndexResult<SearchResults> {
+        self.inner.list(pagination).await
+    }
+}
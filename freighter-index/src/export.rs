@@ -0,0 +1,127 @@
+//! Export of the index as a static sparse index.
+//!
+//! The exported index mirrors what freighter serves over HTTP: a `config.json` at the root, and
+//! one file per crate laid out as described in [`sparse_entry_path`].
+//! It can be written into a local directory with [`DirectoryTarget`] and served by any static file
+//! server, or written into object storage through another [`ExportTarget`] as a read-only replica
+//! or backup of the registry.
+//!
+//! Exports can be incremental: passing the watermark returned by a previous export only rewrites
+//! the entries of crates which changed since then.
+//! Changes are found with [`IndexProvider::list_sparse_entry_changes`], which also catches
+//! changes that were still being committed while the previous export ran.
+
+use crate::{sparse_entry_path, IndexError, IndexProvider, IndexResult, RegistryConfig};
+use anyhow::Context;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Destination of an exported sparse index.
+#[async_trait]
+pub trait ExportTarget {
+    /// Write a file, given its path relative to the root of the index, replacing any existing
+    /// file.
+    ///
+    /// Readers of the target MUST NOT be able to observe a partially written file.
+    async fn write_file(&self, path: &str, contents: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Export target writing into a local directory.
+///
+/// Files are written to a temporary file and renamed into place, so readers of the directory
+/// never observe a partially written entry.
+pub struct DirectoryTarget {
+    root: PathBuf,
+}
+
+impl DirectoryTarget {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl ExportTarget for DirectoryTarget {
+    async fn write_file(&self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        let path = self.root.join(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        write_atomically(&path, contents).await
+    }
+}
+
+/// Summary of a completed export.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// The number of entries written.
+    pub entries: usize,
+    /// The value to pass as `since` to the next incremental export.
+    pub watermark: u64,
+}
+
+/// Export the index into `target` as a static sparse index.
+///
+/// If `since` is set to the watermark of a previous export, only crates which have changed since
+/// that export are written.
+/// `config.json` is always (re)written.
+pub async fn export_sparse_index<I, T>(
+    index: &I,
+    config: &RegistryConfig,
+    target: &T,
+    since: Option<u64>,
+) -> IndexResult<ExportSummary>
+where
+    I: IndexProvider + ?Sized,
+    T: ExportTarget + Sync + ?Sized,
+{
+    let config = serde_json::to_vec(config).context("Failed to serialize registry config")?;
+
+    target.write_file("config.json", &config).await?;
+
+    let changes = index.list_sparse_entry_changes(since).await?;
+
+    let mut summary = ExportSummary {
+        entries: 0,
+        watermark: changes.watermark,
+    };
+
+    for crate_revision in changes.crates {
+        let entry = match index.get_rendered_sparse_entry(&crate_revision.name).await {
+            Ok(entry) => entry,
+            // crates without any versions don't have an entry
+            Err(IndexError::NotFound) => continue,
+            Err(error) => return Err(error),
+        };
+
+        target
+            .write_file(&sparse_entry_path(&crate_revision.name), &entry.contents)
+            .await?;
+
+        summary.entries += 1;
+
+        tracing::debug!(crate_name = crate_revision.name, "Exported sparse entry");
+    }
+
+    Ok(summary)
+}
+
+async fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Failed to move {} into place", path.display()))?;
+
+    Ok(())
+}
@@ -1,20 +1,34 @@
+use anyhow::Context;
 use async_trait::async_trait;
 use semver::Version;
+use time::Date;
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 
 #[cfg(feature = "postgresql-backend")]
 pub mod postgres_client;
 
+#[cfg(feature = "caching")]
+pub mod caching;
+
+#[cfg(feature = "export")]
+pub mod export;
+
+pub mod resolve;
+
 mod api_types;
 
 mod error;
 
+mod sparse;
+
 pub type IndexResult<T> = Result<T, IndexError>;
 
 pub use api_types::*;
 pub use error::*;
+pub use sparse::*;
 
 /// A client for talking with a backing index database or storage medium.
 ///
@@ -35,18 +49,85 @@ pub trait IndexProvider: Sync {
     /// If an error occurs while trying to generate the sparse entry, [`IndexError::ServiceError`]
     /// will be returned.
     async fn get_sparse_entry(&self, crate_name: &str) -> IndexResult<Vec<CrateVersion>>;
+    /// Get the current revision of a crate's sparse index entry, for use in HTTP caching.
+    ///
+    /// The revision MUST change whenever the contents of the sparse entry change, such as when a
+    /// version is published or yanked.
+    ///
+    /// A default implementation is provided which returns [`None`], indicating that the index
+    /// does not track revisions.
+    /// Callers should fall back to inspecting the contents of the entry in that case.
+    async fn get_sparse_entry_revision(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<Option<SparseEntryRevision>> {
+        let _ = crate_name;
+        Ok(None)
+    }
+    /// Get the sparse index entry for a crate rendered in the format served to cargo, along with
+    /// its revision.
+    ///
+    /// If the crate could not be found in the index, [`IndexError::NotFound`] will be returned.
+    ///
+    /// A default implementation is provided which renders the result of
+    /// [`IndexProvider::get_sparse_entry`].
+    /// Indexes which store rendered entries should override it to avoid parsing and serializing
+    /// them again.
+    async fn get_rendered_sparse_entry(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<RenderedSparseEntry> {
+        // looking up the revision first means that it can only ever be older than the contents,
+        // which at worst causes a client to fetch the entry again
+        let revision = self.get_sparse_entry_revision(crate_name).await?;
+        let versions = self.get_sparse_entry(crate_name).await?;
+
+        let contents = render_sparse_entry(&versions).context("Failed to render sparse entry")?;
+
+        Ok(RenderedSparseEntry {
+            contents: contents.into(),
+            revision,
+        })
+    }
+    /// List the crates in the index which have a sparse entry, along with the revision of that
+    /// entry.
+    ///
+    /// If `since` is the watermark of a previous listing, only crates whose entries may have
+    /// changed after that listing are returned.
+    /// Every change which wasn't visible to the previous listing MUST be included, even if it was
+    /// started before that listing, while crates MAY be listed again without having changed.
+    ///
+    /// A default implementation is provided which returns [`IndexError::ServiceError`],
+    /// indicating that the index does not track changes.
+    async fn list_sparse_entry_changes(
+        &self,
+        since: Option<u64>,
+    ) -> IndexResult<SparseEntryChanges> {
+        let _ = since;
+        Err(anyhow::anyhow!("Index does not track changes to sparse entries").into())
+    }
     /// Confirm that a particular crate and version pair exists, and return its yank status
     async fn confirm_existence(&self, crate_name: &str, version: &Version) -> IndexResult<bool>;
     /// Yank a crate version.
     async fn yank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()>;
     /// Unyank a crate version
     async fn unyank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()>;
-    /// Search the index for crates satisfying a query string, returning up to `limit` results.
+    /// Search the index for crates satisfying a query string and filters, returning up to `limit`
+    /// results in order of relevance.
     ///
-    /// The syntax and semantics of the search are up to the implementation to define.
-    async fn search(&self, query_string: &str, limit: usize) -> IndexResult<SearchResults>;
+    /// The syntax and semantics of the search are up to the implementation to define, but an
+    /// empty query string should match every crate passing the filters.
+    async fn search(
+        &self,
+        query_string: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> IndexResult<SearchResults>;
     /// Publish a crate version.
     ///
+    /// `publisher` is the name of the user publishing the version, if known, and is recorded
+    /// along with the rest of the version's metadata.
+    ///
     /// `end_step` is a future to run after the crate has been submitted to the index, but before
     /// any transactional commits have occurred.
     /// If it fails, the operation MUST be rolled back.
@@ -54,10 +135,107 @@ pub trait IndexProvider: Sync {
         &self,
         version: &Publish,
         checksum: &str,
+        publisher: Option<&str>,
         end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
     ) -> IndexResult<CompletedPublication>;
-    /// List crates in the index, optionally specifying pagination.
+    /// Publish a crate version mirrored from another registry, identified by its index URL.
+    ///
+    /// This behaves like [`IndexProvider::publish`], but also marks the crate as mirrored from
+    /// `source`.
+    /// Mirrored crates MUST NOT be published to with [`IndexProvider::publish`], and crates which
+    /// have been published to locally MUST NOT be mirrored, in either case returning
+    /// [`IndexError::Conflict`].
+    ///
+    /// A default implementation is provided which returns [`IndexError::ServiceError`],
+    /// indicating that the index does not support mirroring.
+    async fn publish_mirrored(
+        &self,
+        version: &Publish,
+        checksum: &str,
+        source: &str,
+        end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+    ) -> IndexResult<CompletedPublication> {
+        let _ = (version, checksum, source, end_step);
+        Err(anyhow::anyhow!("Index does not support mirroring").into())
+    }
+    /// Get information about a crate along with every version of it, in the shape of the
+    /// crates.io API.
+    ///
+    /// If the crate could not be found in the index, [`IndexError::NotFound`] will be returned.
+    async fn get_crate(&self, crate_name: &str) -> IndexResult<CrateResponse>;
+    /// Get information about every version of a crate, newest first.
+    ///
+    /// If the crate could not be found in the index, [`IndexError::NotFound`] will be returned.
+    async fn list_versions(&self, crate_name: &str) -> IndexResult<Vec<VersionMetadata>>;
+    /// Get information about a particular version of a crate.
+    ///
+    /// If the version could not be found in the index, [`IndexError::NotFound`] will be returned.
+    async fn get_version_metadata(
+        &self,
+        crate_name: &str,
+        version: &Version,
+    ) -> IndexResult<VersionMetadata>;
+    /// Get the licenses of several crate versions at once.
+    ///
+    /// Versions which could not be found in the index are omitted from the result.
+    ///
+    /// A default implementation is provided which looks up each version with
+    /// [`IndexProvider::get_version_metadata`].
+    /// Indexes should override it to look them up together.
+    async fn get_licenses(
+        &self,
+        packages: &[PackageId],
+    ) -> IndexResult<HashMap<PackageId, Option<String>>> {
+        let mut licenses = HashMap::new();
+
+        for package in packages {
+            match self
+                .get_version_metadata(&package.name, &package.version)
+                .await
+            {
+                Ok(metadata) => {
+                    licenses.insert(package.clone(), metadata.license);
+                }
+                Err(IndexError::NotFound) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(licenses)
+    }
+    /// Get the README of a crate version.
+    ///
+    /// If the version could not be found in the index, or it has no README,
+    /// [`IndexError::NotFound`] will be returned.
+    async fn get_readme(&self, crate_name: &str, version: &Version) -> IndexResult<VersionReadme>;
+    /// Add to the download counts of crate versions, counting them as downloaded on the current
+    /// day.
+    ///
+    /// Downloads of versions which aren't in the index should be ignored.
+    async fn record_downloads(&self, downloads: &[DownloadCount]) -> IndexResult<()>;
+    /// Get the number of downloads of each version of a crate per day, from `since` onwards, in
+    /// ascending order of day.
+    ///
+    /// Days on which a version wasn't downloaded may be omitted.
+    ///
+    /// If the crate could not be found in the index, [`IndexError::NotFound`] will be returned.
+    async fn get_version_downloads(
+        &self,
+        crate_name: &str,
+        since: Date,
+    ) -> IndexResult<Vec<VersionDownloads>>;
+    /// List the crates which depend on a crate, optionally specifying pagination.
+    ///
+    /// If no page size is provided, all reverse dependencies should be returned.
+    ///
+    /// If the crate could not be found in the index, [`IndexError::NotFound`] will be returned.
+    async fn list_reverse_dependencies(
+        &self,
+        crate_name: &str,
+        pagination: &ReverseDependenciesQuery,
+    ) -> IndexResult<ReverseDependencies>;
+    /// List crates in the index in the requested order, optionally specifying pagination.
     ///
-    /// If no pagination is provided, all crates should be returned.
-    async fn list(&self, pagination: &ListQuery) -> IndexResult<Vec<SearchResultsEntry>>;
+    /// If no page size is provided, all crates should be returned.
+    async fn list(&self, pagination: &ListQuery) -> IndexResult<SearchResults>;
 }
@@ -1,22 +1,35 @@
 use crate::{
-    CompletedPublication, CrateVersion, Dependency, IndexError, IndexProvider, IndexResult,
-    ListQuery, Publish, SearchResults, SearchResultsEntry, SearchResultsMeta,
+    is_extended_feature, parse_sparse_entry, render_sparse_entry, CompletedPublication,
+    CrateMetadata, CrateResponse, CrateRevision, CrateVersion, DownloadCount, IndexChange,
+    IndexError, IndexProvider, IndexResult, ListQuery, ListSeek, PackageId, Publish,
+    RenderedSparseEntry, ReverseDependencies, ReverseDependenciesMeta, ReverseDependenciesQuery,
+    ReverseDependency, SearchFilters, SearchResults, SearchResultsEntry, SearchResultsMeta,
+    SparseEntryChanges, SparseEntryRevision, VersionDownloads, VersionMetadata, VersionPublisher,
+    VersionReadme,
 };
 use anyhow::Context;
 use async_trait::async_trait;
-use deadpool_postgres::tokio_postgres::{IsolationLevel, NoTls, Row, Statement};
-use deadpool_postgres::{Pool, Runtime};
-use futures_util::StreamExt;
+use deadpool_postgres::tokio_postgres::{AsyncMessage, IsolationLevel, NoTls, Row};
+use deadpool_postgres::{tokio_postgres, Pool, Runtime, Transaction};
+use futures_util::{Stream, StreamExt};
 use metrics::histogram;
-use postgres_types::ToSql;
+use postgres_types::Json;
 use semver::{Version, VersionReq};
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
 use std::time::Instant;
+use time::Date;
+use tokio::sync::mpsc;
 
+/// The channel on which changes to crates are announced via `NOTIFY`.
+const CHANGES_CHANNEL: &str = "freighter_index_changes";
+
+#[derive(Clone)]
 pub struct PgIndexProvider {
     pool: Pool,
+    pg_config: tokio_postgres::Config,
 }
 
 impl PgIndexProvider {
@@ -25,211 +38,166 @@ impl PgIndexProvider {
             .create_pool(Some(Runtime::Tokio1), NoTls)
             .context("Failed to create db pool")?;
 
-        Ok(Self { pool })
-    }
+        let pg_config = config
+            .get_pg_config()
+            .context("Failed to construct db config")?;
 
-    async fn yank_inner(&self, crate_name: &str, version: &Version, val: bool) -> IndexResult<()> {
-        let client = self.pool.get().await.unwrap();
+        Ok(Self { pool, pg_config })
+    }
 
-        let statement = client
-            .prepare_cached(include_str!("../sql/set-yank.sql"))
+    /// Subscribe to changes made to crates in the index.
+    ///
+    /// Every publish, yank, and unyank performed by any [`PgIndexProvider`] connected to the same
+    /// database is announced once its transaction commits, which allows caches in front of the
+    /// index to be kept coherent across several instances of freighter.
+    ///
+    /// This opens a dedicated connection to the database.
+    /// If that connection is lost, an error is yielded and the stream ends.
+    /// Changes which happen while no subscription is active are not replayed, so callers should
+    /// treat the loss of a subscription as a reason to invalidate everything.
+    pub async fn subscribe(&self) -> IndexResult<IndexChanges> {
+        let (client, mut connection) = self
+            .pg_config
+            .connect(NoTls)
             .await
-            .context("Failed to prepare yank/unyank statement")?;
+            .context("Failed to connect to db for change subscription")?;
 
-        let rows = client
-            .query(&statement, &[&crate_name, &version.to_string(), &val])
-            .await
-            .context("Failed to execute yank/unyank query")?;
+        let (sender, receiver) = mpsc::unbounded_channel();
 
-        assert!(rows.len() <= 1);
+        // notifications are only surfaced by polling the connection directly
+        tokio::spawn(async move {
+            let mut messages = futures_util::stream::poll_fn(|cx| connection.poll_message(cx));
 
-        if rows.len() == 1 {
-            Ok(())
-        } else {
-            Err(IndexError::Conflict(
-                "Tried to set yank status to an identical status".to_string(),
-            ))
-        }
-    }
-}
+            while let Some(message) = messages.next().await {
+                let change = match message {
+                    Ok(AsyncMessage::Notification(notification)) => Ok(IndexChange {
+                        crate_name: notification.payload().to_string(),
+                    }),
+                    Ok(_) => continue,
+                    Err(error) => Err(IndexError::ServiceError(
+                        anyhow::Error::new(error).context("Change subscription connection failed"),
+                    )),
+                };
 
-#[async_trait]
-impl IndexProvider for PgIndexProvider {
-    async fn get_sparse_entry(&self, crate_name: &str) -> IndexResult<Vec<CrateVersion>> {
-        let client = self.pool.get().await.unwrap();
+                let failed = change.is_err();
 
-        // prepare these at once to take advantage of pipelining
-        let (existential_statement, versions_statement, features_statement, dependencies_statement) =
-            tokio::try_join!(
-                client.prepare_cached(include_str!("../sql/sparse-index/get-crate.sql")),
-                client.prepare_cached(include_str!("../sql/sparse-index/get-versions.sql")),
-                client.prepare_cached(include_str!("../sql/sparse-index/get-features.sql")),
-                client.prepare_cached(include_str!("../sql/sparse-index/get-dependencies.sql"))
-            )
-            .context("Failed to prepare transaction")?;
+                if sender.send(change).is_err() || failed {
+                    break;
+                }
+            }
+        });
 
-        match client
-            .query_one(&existential_statement, &[&crate_name])
+        client
+            .batch_execute(&format!("listen {CHANGES_CHANNEL}"))
             .await
-        {
-            Ok(crate_row) => {
-                let id: i32 = crate_row.get("id");
+            .context("Failed to listen for index changes")?;
 
-                // this is a major hotpath
-                let version_rows = client
-                    .query(&versions_statement, &[&id])
-                    .await
-                    .context("Failed to query versions")?;
+        Ok(IndexChanges {
+            _client: client,
+            receiver,
+        })
+    }
 
-                let mut versions = Vec::with_capacity(version_rows.len());
+    /// Render the sparse entry of every crate in the index again, storing those which changed.
+    ///
+    /// Entries are only rendered when crates are published to or yanked, so this needs to be run
+    /// after upgrading to a version of freighter which renders them differently.
+    /// Crates whose entries changed get a new revision and are announced to subscribers, like any
+    /// other change.
+    ///
+    /// Returns the number of entries which changed.
+    pub async fn rematerialize_sparse_entries(&self) -> IndexResult<usize> {
+        let client = self.pool.get().await.unwrap();
 
-                // drive them all concurrently to improve pipelining
-                let mut version_queries = futures_util::stream::FuturesUnordered::new();
+        let statement = client
+            .prepare_cached(include_str!("../sql/sparse-index/list-crates.sql"))
+            .await
+            .context("Failed to prepare crate listing statement")?;
 
-                // using a function like this can often make rustc a bit smarter about what it captures and generates
-                async fn query_version(
-                    version_row: Row,
-                    client: &deadpool_postgres::Client,
-                    features_statement: &Statement,
-                    dependencies_statement: &Statement,
-                ) -> anyhow::Result<(Row, Vec<Row>, Vec<Row>)> {
-                    let version_id: i32 = version_row.get("id");
+        let crates = client
+            .query(&statement, &[])
+            .await
+            .context("Failed to list crates")?;
 
-                    // this shouldn't be necessary but it is nonetheless
-                    let version_id_query = [&version_id as &(dyn ToSql + Sync)];
+        drop(client);
 
-                    // pipeline the queries here too
-                    let (features_row, dependencies_row) = tokio::try_join!(
-                        client.query(features_statement, &version_id_query),
-                        client.query(dependencies_statement, &version_id_query)
-                    )
-                    .context("Failed to query features or dependencies for crate")?;
+        let mut changed = 0;
 
-                    Ok((version_row, features_row, dependencies_row))
-                }
+        // each crate gets its own transaction, so that publishes aren't held up for long
+        for row in crates {
+            let crate_id: i32 = row.get("id");
+            let crate_name: String = row.get("name");
 
-                for version_row in version_rows {
-                    version_queries.push(query_version(
-                        version_row,
-                        &client,
-                        &features_statement,
-                        &dependencies_statement,
-                    ));
-                }
-
-                while let Some(query_res) = version_queries.next().await {
-                    let (version_row, feature_rows, dependency_rows) = query_res?;
-
-                    let mut features = HashMap::with_capacity(feature_rows.len());
-                    let mut deps = Vec::with_capacity(dependency_rows.len());
-
-                    for feature_row in feature_rows {
-                        features.insert(feature_row.get("name"), feature_row.get("values"));
-                    }
-
-                    for deps_row in dependency_rows {
-                        deps.push(Dependency {
-                            name: deps_row.get("name"),
-                            req: VersionReq::parse(deps_row.get("req"))
-                                .context("Failed to parse dependency version req in db")?,
-                            features: deps_row.get("features"),
-                            optional: deps_row.get("optional"),
-                            default_features: deps_row.get("default_features"),
-                            target: deps_row.get("target"),
-                            kind: deps_row.get("kind"),
-                            registry: deps_row.get("registry"),
-                            package: deps_row.get("package"),
-                        });
-                    }
-
-                    versions.push(CrateVersion {
-                        name: crate_name.to_string(),
-                        vers: Version::parse(version_row.get("version"))
-                            .context("Failed to parse crate version in db")?,
-                        deps,
-                        cksum: version_row.get("cksum"),
-                        features,
-                        yanked: version_row.get("yanked"),
-                        links: version_row.get("links"),
-                        v: 2,
-                        // todo maybe scrap
-                        features2: HashMap::new(),
-                    });
-                }
+            if self
+                .rematerialize_sparse_entry(crate_id, &crate_name)
+                .await
+                .with_context(|| format!("Failed to rematerialize sparse entry of {crate_name}"))?
+            {
+                tracing::info!(crate_name, "Rematerialized sparse entry");
 
-                Ok(versions)
-            }
-            Err(error) => {
-                tracing::warn!(?error, "Returning 404 for crate index");
-                Err(IndexError::NotFound)
+                changed += 1;
             }
         }
+
+        Ok(changed)
     }
 
-    async fn confirm_existence(&self, crate_name: &str, version: &Version) -> IndexResult<bool> {
-        let client = self.pool.get().await.unwrap();
+    async fn rematerialize_sparse_entry(
+        &self,
+        crate_id: i32,
+        crate_name: &str,
+    ) -> anyhow::Result<bool> {
+        let mut client = self.pool.get().await.unwrap();
 
-        let statement = client
-            .prepare_cached(include_str!("../sql/confirm-existence.sql"))
+        let transaction = client
+            .transaction()
             .await
-            .context("Failed to prepare confirm existence statement")?;
+            .context("Failed to create rematerialization transaction")?;
 
-        let rows: Vec<Row> = client
-            .query(&statement, &[&crate_name, &version.to_string()])
+        let (lock_statement, bump_crate_revision_statement, notify_change_statement) =
+            tokio::try_join!(
+                transaction.prepare_cached(include_str!("../sql/sparse-index/lock-crate.sql")),
+                transaction.prepare_cached(include_str!("../sql/publish/bump-crate-revision.sql")),
+                transaction.prepare_cached(include_str!("../sql/notify-change.sql")),
+            )
+            .context("Failed to prepare rematerialization statements")?;
+
+        // a concurrent publish or yank could otherwise be overwritten with an older entry
+        transaction
+            .query(&lock_statement, &[&crate_id])
             .await
-            .context("Failed to execute existential confirmation query")?;
+            .context("Failed to lock crate")?;
 
-        if let Some(row) = rows.first() {
-            Ok(row.get("yanked"))
-        } else {
-            Err(IndexError::NotFound)
+        if !materialize_sparse_entry(&transaction, crate_id, crate_name).await? {
+            return Ok(false);
         }
-    }
 
-    async fn yank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
-        self.yank_inner(crate_name, version, true).await
-    }
-
-    async fn unyank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
-        self.yank_inner(crate_name, version, false).await
-    }
-
-    async fn search(&self, query_string: &str, limit: usize) -> IndexResult<SearchResults> {
-        let client = self.pool.get().await.unwrap();
-
-        let statement = client
-            .prepare_cached(include_str!("../sql/search.sql"))
+        transaction
+            .query(&bump_crate_revision_statement, &[&crate_id])
             .await
-            .context("Failed to prepare search statement")?;
+            .context("Failed to bump crate revision")?;
 
-        let mut rows: Vec<Row> = client
-            .query(&statement, &[&query_string])
+        transaction
+            .execute(&notify_change_statement, &[&CHANGES_CHANNEL, &crate_name])
             .await
-            .context("Failed to execute search query")?;
-
-        // return the client immediately to the pool in case sorting takes longer than we'd like
-        drop(client);
-
-        // we can't scale the DB as easily as we can this server, so let's sort in here
-        // warning: may be expensive!
-        rows.sort_unstable_by_key(|r| (r.get::<_, i64>("count"), r.get::<_, String>("name")));
+            .context("Failed to notify of rematerialization")?;
 
-        let total = rows.len();
-
-        // also might be expensive
-        let crates = rows.iter().take(limit).map(search_row_to_entry).collect();
-
-        let meta = SearchResultsMeta { total };
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit rematerialization transaction")?;
 
-        Ok(SearchResults { crates, meta })
+        Ok(true)
     }
 
     // this one has a lot of optimization headroom, and is thus perfect for experiments
     // sadly it does not matter, as this will never be as slow for the user as compiling the crate
-    async fn publish(
+    async fn publish_inner(
         &self,
         version: &Publish,
         checksum: &str,
+        publisher: Option<&str>,
+        mirrored_from: Option<&str>,
         end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
     ) -> IndexResult<CompletedPublication> {
         let startup_timer = Instant::now();
@@ -253,6 +221,7 @@ impl IndexProvider for PgIndexProvider {
             insert_dependency_statement,
             insert_features_statement,
             update_crate_statement,
+            bump_crate_revision_statement,
             get_crate_keywords_statement,
             get_crate_categories_statement,
             insert_keyword_statement,
@@ -261,12 +230,17 @@ impl IndexProvider for PgIndexProvider {
             insert_crate_category_statement,
             remove_crate_keyword_statement,
             remove_crate_category_statement,
+            check_crate_versions_statement,
+            set_crate_mirrored_statement,
+            update_search_vector_statement,
+            notify_change_statement,
         ) = tokio::try_join!(
             transaction.prepare_cached(include_str!("../sql/publish/get-or-insert-crate.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/insert-version.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/insert-dependency.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/insert-features.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/update-crate.sql")),
+            transaction.prepare_cached(include_str!("../sql/publish/bump-crate-revision.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/get-crate-keywords.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/get-crate-categories.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/insert-keyword.sql")),
@@ -275,6 +249,10 @@ impl IndexProvider for PgIndexProvider {
             transaction.prepare_cached(include_str!("../sql/publish/insert-crate-category.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/remove-crate-keyword.sql")),
             transaction.prepare_cached(include_str!("../sql/publish/remove-crate-category.sql")),
+            transaction.prepare_cached(include_str!("../sql/publish/check-crate-versions.sql")),
+            transaction.prepare_cached(include_str!("../sql/publish/set-crate-mirrored.sql")),
+            transaction.prepare_cached(include_str!("../sql/publish/update-search-vector.sql")),
+            transaction.prepare_cached(include_str!("../sql/notify-change.sql")),
         )
         .context("Failed to prepare statements for publish transaction")?;
 
@@ -292,6 +270,41 @@ impl IndexProvider for PgIndexProvider {
 
         let crate_id: i32 = crate_row.get("id");
 
+        // mirrored crates are owned by their source registry, and must not be mixed with local ones
+        let crate_mirrored_from: Option<String> = crate_row.get("mirrored_from");
+
+        match (mirrored_from, crate_mirrored_from.as_deref()) {
+            (None, Some(source)) => {
+                return Err(IndexError::Conflict(format!(
+                    "Crate is mirrored from {source} and cannot be published to directly"
+                )));
+            }
+            (Some(source), Some(existing)) if source != existing => {
+                return Err(IndexError::Conflict(format!(
+                    "Crate is already mirrored from {existing}"
+                )));
+            }
+            (Some(source), None) => {
+                let has_versions: bool = transaction
+                    .query_one(&check_crate_versions_statement, &[&crate_id])
+                    .await
+                    .context("Failed to check for existing crate versions")?
+                    .get("exists");
+
+                if has_versions {
+                    return Err(IndexError::Conflict(
+                        "Crate has been published locally and cannot be mirrored".to_string(),
+                    ));
+                }
+
+                transaction
+                    .execute(&set_crate_mirrored_statement, &[&crate_id, &source])
+                    .await
+                    .context("Failed to mark crate as mirrored")?;
+            }
+            _ => {}
+        }
+
         // postgres will replace the whole row anyways, so lets just be slightly more convenient
         if version.description != crate_row.get("description")
             || version.documentation != crate_row.get("documentation")
@@ -407,6 +420,18 @@ impl IndexProvider for PgIndexProvider {
             "component" => "prune_keycat"
         );
 
+        let search_vector_timer = Instant::now();
+
+        transaction
+            .execute(&update_search_vector_statement, &[&crate_id])
+            .await
+            .context("Failed to update crate search vector")?;
+
+        histogram!(
+            "publish_component_duration_seconds", search_vector_timer.elapsed(),
+            "component" => "search_vector"
+        );
+
         let insert_version_timer = Instant::now();
 
         let insert_version_row = transaction
@@ -418,6 +443,14 @@ impl IndexProvider for PgIndexProvider {
                     &checksum,
                     &false,
                     &version.links,
+                    &version.rust_version,
+                    &version.description,
+                    &version.license,
+                    &version.license_file,
+                    &version.authors,
+                    &version.readme,
+                    &version.readme_file,
+                    &publisher,
                 ],
             )
             .await
@@ -464,7 +497,12 @@ impl IndexProvider for PgIndexProvider {
             transaction
                 .query_one(
                     &insert_features_statement,
-                    &[&version_id, &feature.0, &feature.1],
+                    &[
+                        &version_id,
+                        &feature.0,
+                        &feature.1,
+                        &is_extended_feature(feature.1),
+                    ],
                 )
                 .await
                 .context("Failed to insert feature")?;
@@ -475,6 +513,28 @@ impl IndexProvider for PgIndexProvider {
             "component" => "insert_features"
         );
 
+        transaction
+            .query(&bump_crate_revision_statement, &[&crate_id])
+            .await
+            .context("Failed to bump crate revision")?;
+
+        let materialize_timer = Instant::now();
+
+        materialize_sparse_entry(&transaction, crate_id, &version.name)
+            .await
+            .context("Failed to materialize sparse entry")?;
+
+        histogram!(
+            "publish_component_duration_seconds", materialize_timer.elapsed(),
+            "component" => "materialize"
+        );
+
+        // this will only be delivered once the transaction commits
+        transaction
+            .execute(&notify_change_statement, &[&CHANGES_CHANNEL, &version.name])
+            .await
+            .context("Failed to notify of publication")?;
+
         let end_step_timer = Instant::now();
 
         end_step
@@ -501,43 +561,689 @@ impl IndexProvider for PgIndexProvider {
         Ok(CompletedPublication { warnings: None })
     }
 
-    async fn list(&self, pagination: &ListQuery) -> IndexResult<Vec<SearchResultsEntry>> {
+    async fn yank_inner(&self, crate_name: &str, version: &Version, val: bool) -> IndexResult<()> {
+        let mut client = self.pool.get().await.unwrap();
+
+        let transaction = client
+            .transaction()
+            .await
+            .context("Failed to create yank/unyank transaction")?;
+
+        let (statement, notify_statement) = tokio::try_join!(
+            transaction.prepare_cached(include_str!("../sql/set-yank.sql")),
+            transaction.prepare_cached(include_str!("../sql/notify-change.sql")),
+        )
+        .context("Failed to prepare yank/unyank statements")?;
+
+        let rows = transaction
+            .query(&statement, &[&crate_name, &version.to_string(), &val])
+            .await
+            .context("Failed to execute yank/unyank query")?;
+
+        assert!(rows.len() <= 1);
+
+        if let Some(row) = rows.first() {
+            materialize_sparse_entry(&transaction, row.get("id"), crate_name)
+                .await
+                .context("Failed to materialize sparse entry after yank/unyank")?;
+
+            transaction
+                .execute(&notify_statement, &[&CHANGES_CHANNEL, &crate_name])
+                .await
+                .context("Failed to notify of yank/unyank")?;
+
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit yank/unyank transaction")?;
+
+            Ok(())
+        } else {
+            Err(IndexError::Conflict(
+                "Tried to set yank status to an identical status".to_string(),
+            ))
+        }
+    }
+
+    /// Query the versions of a crate, newest first, optionally restricted to a single version.
+    async fn query_versions(
+        &self,
+        crate_name: &str,
+        version: Option<&Version>,
+    ) -> IndexResult<Vec<VersionMetadata>> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/metadata/list-versions.sql"))
+            .await
+            .context("Failed to prepare version listing statement")?;
+
+        let version = version.map(Version::to_string);
+
+        let versions = client
+            .query(&statement, &[&crate_name, &version])
+            .await
+            .context("Failed to execute version listing query")?
+            .iter()
+            .map(version_row_to_metadata)
+            .collect();
+
+        Ok(versions)
+    }
+}
+
+/// Stream of changes to crates in the index.
+///
+/// See [`PgIndexProvider::subscribe`] for more information.
+pub struct IndexChanges {
+    // dropping the client closes the connection, so it must be kept around
+    _client: tokio_postgres::Client,
+    receiver: mpsc::UnboundedReceiver<IndexResult<IndexChange>>,
+}
+
+impl Stream for IndexChanges {
+    type Item = IndexResult<IndexChange>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[async_trait]
+impl IndexProvider for PgIndexProvider {
+    async fn get_sparse_entry(&self, crate_name: &str) -> IndexResult<Vec<CrateVersion>> {
+        let entry = self.get_rendered_sparse_entry(crate_name).await?;
+
+        let contents = std::str::from_utf8(&entry.contents)
+            .context("Materialized sparse entry is not UTF-8")?;
+
+        let versions =
+            parse_sparse_entry(contents).context("Failed to parse materialized sparse entry")?;
+
+        Ok(versions)
+    }
+
+    async fn get_rendered_sparse_entry(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<RenderedSparseEntry> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/sparse-index/get-entry.sql"))
+            .await
+            .context("Failed to prepare sparse entry statement")?;
+
+        // this is a major hotpath, so entries are rendered ahead of time on publish and yank
+        let rows = client
+            .query(&statement, &[&crate_name])
+            .await
+            .context("Failed to query sparse entry")?;
+
+        drop(client);
+
+        let Some(row) = rows.first() else {
+            tracing::warn!("Returning 404 for crate index");
+            return Err(IndexError::NotFound);
+        };
+
+        Ok(RenderedSparseEntry {
+            contents: row.get::<_, String>("entry").into(),
+            revision: Some(SparseEntryRevision {
+                revision: row.get::<_, i64>("revision") as u64,
+                last_modified: row.get("updated_at"),
+            }),
+        })
+    }
+
+    async fn get_sparse_entry_revision(
+        &self,
+        crate_name: &str,
+    ) -> IndexResult<Option<SparseEntryRevision>> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/sparse-index/get-revision.sql"))
+            .await
+            .context("Failed to prepare revision statement")?;
+
+        let rows = client
+            .query(&statement, &[&crate_name])
+            .await
+            .context("Failed to execute revision query")?;
+
+        if let Some(row) = rows.first() {
+            Ok(Some(SparseEntryRevision {
+                revision: row.get::<_, i64>("revision") as u64,
+                last_modified: row.get("updated_at"),
+            }))
+        } else {
+            Err(IndexError::NotFound)
+        }
+    }
+
+    async fn list_sparse_entry_changes(
+        &self,
+        since: Option<u64>,
+    ) -> IndexResult<SparseEntryChanges> {
+        let mut client = self.pool.get().await.unwrap();
+
+        // the watermark and the listing must come from the same snapshot
+        let transaction = client
+            .build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()
+            .await
+            .context("Failed to create change listing transaction")?;
+
+        let (watermark_statement, list_statement) = tokio::try_join!(
+            transaction.prepare_cached(include_str!("../sql/sparse-index/get-watermark.sql")),
+            transaction.prepare_cached(include_str!("../sql/sparse-index/list-changes.sql")),
+        )
+        .context("Failed to prepare change listing statements")?;
+
+        // revisions are taken before a change commits, so changes can become visible out of order
+        // instead, every transaction older than the oldest one still running has finished and is
+        // visible in this snapshot, while anything which commits later has at least that id
+        let watermark: i64 = transaction
+            .query_one(&watermark_statement, &[])
+            .await
+            .context("Failed to execute watermark query")?
+            .get("watermark");
+
+        let since = since.map(|since| since as i64);
+
+        let crates = transaction
+            .query(&list_statement, &[&since])
+            .await
+            .context("Failed to execute change listing query")?
+            .iter()
+            .map(|row| CrateRevision {
+                name: row.get("name"),
+                revision: SparseEntryRevision {
+                    revision: row.get::<_, i64>("revision") as u64,
+                    last_modified: row.get("updated_at"),
+                },
+            })
+            .collect();
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit change listing transaction")?;
+
+        Ok(SparseEntryChanges {
+            crates,
+            watermark: watermark as u64,
+        })
+    }
+
+    async fn confirm_existence(&self, crate_name: &str, version: &Version) -> IndexResult<bool> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/confirm-existence.sql"))
+            .await
+            .context("Failed to prepare confirm existence statement")?;
+
+        let rows: Vec<Row> = client
+            .query(&statement, &[&crate_name, &version.to_string()])
+            .await
+            .context("Failed to execute existential confirmation query")?;
+
+        if let Some(row) = rows.first() {
+            Ok(row.get("yanked"))
+        } else {
+            Err(IndexError::NotFound)
+        }
+    }
+
+    async fn yank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
+        self.yank_inner(crate_name, version, true).await
+    }
+
+    async fn unyank_crate(&self, crate_name: &str, version: &Version) -> IndexResult<()> {
+        self.yank_inner(crate_name, version, false).await
+    }
+
+    async fn search(
+        &self,
+        query_string: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> IndexResult<SearchResults> {
         let client = self.pool.get().await.unwrap();
 
         let statement = client
-            .prepare_cached(include_str!("../sql/list.sql"))
+            .prepare_cached(include_str!("../sql/search.sql"))
             .await
             .context("Failed to prepare search statement")?;
 
-        let mut rows: Vec<Row> = client
-            .query(&statement, &[])
+        let rows: Vec<Row> = client
+            .query(
+                &statement,
+                &[
+                    &query_string,
+                    &filters.keyword,
+                    &filters.category,
+                    &filters.crates,
+                    &(limit as i64),
+                ],
+            )
             .await
             .context("Failed to execute search query")?;
 
-        // return the client immediately to the pool in case sorting takes longer than we'd like
+        let total = rows
+            .first()
+            .map_or(0, |row| row.get::<_, i64>("total") as usize);
+
+        let crates = rows.iter().map(search_row_to_entry).collect();
+
+        let meta = SearchResultsMeta {
+            total,
+            next_page: None,
+            prev_page: None,
+        };
+
+        Ok(SearchResults { crates, meta })
+    }
+
+    async fn publish(
+        &self,
+        version: &Publish,
+        checksum: &str,
+        publisher: Option<&str>,
+        end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+    ) -> IndexResult<CompletedPublication> {
+        self.publish_inner(version, checksum, publisher, None, end_step)
+            .await
+    }
+
+    async fn publish_mirrored(
+        &self,
+        version: &Publish,
+        checksum: &str,
+        source: &str,
+        end_step: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>,
+    ) -> IndexResult<CompletedPublication> {
+        self.publish_inner(version, checksum, None, Some(source), end_step)
+            .await
+    }
+
+    async fn get_crate(&self, crate_name: &str) -> IndexResult<CrateResponse> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/metadata/get-crate.sql"))
+            .await
+            .context("Failed to prepare crate metadata statement")?;
+
+        let row = client
+            .query_opt(&statement, &[&crate_name])
+            .await
+            .context("Failed to execute crate metadata query")?
+            .ok_or(IndexError::NotFound)?;
+
         drop(client);
 
-        // we can't scale the DB as easily as we can this server, so let's sort in here
-        // warning: may be expensive!
-        rows.sort_unstable_by_key(|r| (r.get::<_, i64>("count"), r.get::<_, String>("name")));
+        let versions = self.list_versions(crate_name).await?;
 
-        let crates = if let ListQuery {
-            per_page: Some(per_page),
-            page,
-        } = pagination
-        {
-            rows.chunks(*per_page)
-                .nth(page.unwrap_or_default())
-                .unwrap_or(&[])
-                .into_iter()
-                .map(search_row_to_entry)
-                .collect()
-        } else {
-            rows.iter().map(search_row_to_entry).collect()
+        let max_version = versions
+            .iter()
+            .filter(|version| !version.yanked)
+            .map(|version| &version.num)
+            .max()
+            .or_else(|| versions.iter().map(|version| &version.num).max())
+            .ok_or(IndexError::NotFound)?
+            .clone();
+
+        let max_stable_version = versions
+            .iter()
+            .filter(|version| !version.yanked && version.num.pre.is_empty())
+            .map(|version| &version.num)
+            .max()
+            .cloned();
+
+        let crate_metadata = CrateMetadata {
+            id: row.get("name"),
+            name: row.get("name"),
+            description: row.get("description"),
+            homepage: row.get("homepage"),
+            documentation: row.get("documentation"),
+            repository: row.get("repository"),
+            max_version,
+            max_stable_version,
+            // versions are listed newest first
+            newest_version: versions[0].num.clone(),
+            downloads: row.get::<_, i64>("downloads") as u64,
+            keywords: row.get("keywords"),
+            categories: row.get("categories"),
+            versions: versions.iter().map(|version| version.id).collect(),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        };
+
+        Ok(CrateResponse {
+            crate_metadata,
+            versions,
+        })
+    }
+
+    async fn list_versions(&self, crate_name: &str) -> IndexResult<Vec<VersionMetadata>> {
+        let versions = self.query_versions(crate_name, None).await?;
+
+        if versions.is_empty() {
+            return Err(IndexError::NotFound);
+        }
+
+        Ok(versions)
+    }
+
+    async fn get_version_metadata(
+        &self,
+        crate_name: &str,
+        version: &Version,
+    ) -> IndexResult<VersionMetadata> {
+        self.query_versions(crate_name, Some(version))
+            .await?
+            .pop()
+            .ok_or(IndexError::NotFound)
+    }
+
+    async fn get_licenses(
+        &self,
+        packages: &[PackageId],
+    ) -> IndexResult<HashMap<PackageId, Option<String>>> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/metadata/get-licenses.sql"))
+            .await
+            .context("Failed to prepare license statement")?;
+
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        let versions: Vec<String> = packages.iter().map(|p| p.version.to_string()).collect();
+
+        let rows = client
+            .query(&statement, &[&names, &versions])
+            .await
+            .context("Failed to execute license query")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let package = PackageId {
+                    name: row.get("name"),
+                    version: Version::parse(row.get("version")).unwrap(),
+                };
+
+                (package, row.get("license"))
+            })
+            .collect())
+    }
+
+    async fn get_readme(&self, crate_name: &str, version: &Version) -> IndexResult<VersionReadme> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/metadata/get-readme.sql"))
+            .await
+            .context("Failed to prepare readme statement")?;
+
+        let row = client
+            .query_opt(&statement, &[&crate_name, &version.to_string()])
+            .await
+            .context("Failed to execute readme query")?
+            .ok_or(IndexError::NotFound)?;
+
+        Ok(VersionReadme {
+            contents: row.get("readme"),
+            file: row.get("readme_file"),
+        })
+    }
+
+    async fn record_downloads(&self, downloads: &[DownloadCount]) -> IndexResult<()> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/downloads/record-downloads.sql"))
+            .await
+            .context("Failed to prepare download recording statement")?;
+
+        let names: Vec<&str> = downloads.iter().map(|d| d.crate_name.as_str()).collect();
+        let versions: Vec<String> = downloads.iter().map(|d| d.version.to_string()).collect();
+        let counts: Vec<i64> = downloads.iter().map(|d| d.downloads as i64).collect();
+
+        client
+            .execute(&statement, &[&names, &versions, &counts])
+            .await
+            .context("Failed to execute download recording query")?;
+
+        Ok(())
+    }
+
+    async fn get_version_downloads(
+        &self,
+        crate_name: &str,
+        since: Date,
+    ) -> IndexResult<Vec<VersionDownloads>> {
+        let client = self.pool.get().await.unwrap();
+
+        let statement = client
+            .prepare_cached(include_str!("../sql/downloads/get-version-downloads.sql"))
+            .await
+            .context("Failed to prepare version downloads statement")?;
+
+        let rows = client
+            .query(&statement, &[&crate_name, &since])
+            .await
+            .context("Failed to execute version downloads query")?;
+
+        if rows.is_empty() {
+            return Err(IndexError::NotFound);
+        }
+
+        // crates without any downloads in the period still produce a single row of nulls
+        let downloads = rows
+            .iter()
+            .filter_map(|row| {
+                Some(VersionDownloads {
+                    version: row.get::<_, Option<i32>>("version")? as u32,
+                    downloads: row.get::<_, i64>("downloads") as u64,
+                    date: row.get("date"),
+                })
+            })
+            .collect();
+
+        Ok(downloads)
+    }
+
+    async fn list_reverse_dependencies(
+        &self,
+        crate_name: &str,
+        pagination: &ReverseDependenciesQuery,
+    ) -> IndexResult<ReverseDependencies> {
+        let client = self.pool.get().await.unwrap();
+
+        let (list_statement, count_statement) = tokio::try_join!(
+            client.prepare_cached(include_str!("../sql/reverse-dependencies/list.sql")),
+            client.prepare_cached(include_str!("../sql/reverse-dependencies/count.sql")),
+        )
+        .context("Failed to prepare reverse dependency statements")?;
+
+        let count = client
+            .query_opt(&count_statement, &[&crate_name])
+            .await
+            .context("Failed to execute reverse dependency count query")?
+            .ok_or(IndexError::NotFound)?;
+
+        let limit = pagination.per_page.map(|per_page| per_page as i64);
+        let offset = pagination
+            .offset()
+            .context("Requested page of reverse dependencies is out of range")?;
+
+        let rows = client
+            .query(&list_statement, &[&crate_name, &limit, &offset])
+            .await
+            .context("Failed to execute reverse dependency query")?;
+
+        let dependencies = rows
+            .iter()
+            .map(|row| ReverseDependency {
+                crate_name: row.get("name"),
+                version: Version::parse(row.get("version")).unwrap(),
+                req: VersionReq::parse(row.get("req")).unwrap(),
+                features: row.get("features"),
+                optional: row.get("optional"),
+                default_features: row.get("default_features"),
+                target: row.get("target"),
+                kind: row.get("kind"),
+                downloads: row.get::<_, i64>("downloads") as u64,
+            })
+            .collect();
+
+        Ok(ReverseDependencies {
+            dependencies,
+            meta: ReverseDependenciesMeta {
+                total: count.get::<_, i64>("total") as usize,
+            },
+        })
+    }
+
+    async fn list(&self, pagination: &ListQuery) -> IndexResult<SearchResults> {
+        let client = self.pool.get().await.unwrap();
+
+        let (list_statement, count_statement) = tokio::try_join!(
+            client.prepare_cached(include_str!("../sql/list.sql")),
+            client.prepare_cached(include_str!("../sql/count-crates.sql")),
+        )
+        .context("Failed to prepare list statements")?;
+
+        let sort = pagination.sort.unwrap_or_default().as_str();
+        let seek_key = pagination.seek.as_ref().map(|seek| seek.key);
+        let seek_name = pagination.seek.as_ref().map(|seek| seek.name.as_str());
+        let limit = pagination.per_page.map(|per_page| per_page as i64);
+        let offset = pagination
+            .offset()
+            .context("Requested page of crates is out of range")?;
+
+        let rows = client
+            .query(
+                &list_statement,
+                &[&sort, &seek_key, &seek_name, &limit, &offset],
+            )
+            .await
+            .context("Failed to execute list query")?;
+
+        let count = client
+            .query_one(&count_statement, &[])
+            .await
+            .context("Failed to execute crate count query")?;
+
+        let crates: Vec<SearchResultsEntry> = rows.iter().map(search_row_to_entry).collect();
+
+        // a short page is the last one, so only a full page can have another after it
+        let next_page = match (rows.last(), pagination.per_page) {
+            (Some(last), Some(per_page)) if rows.len() == per_page => {
+                Some(pagination.next_page(&ListSeek {
+                    key: last.get("sort_key"),
+                    name: last.get("name"),
+                }))
+            }
+            _ => None,
         };
 
-        Ok(crates)
+        let meta = SearchResultsMeta {
+            total: count.get::<_, i64>("total") as usize,
+            next_page,
+            prev_page: pagination.prev_page(),
+        };
+
+        Ok(SearchResults { crates, meta })
+    }
+}
+
+fn version_row_to_metadata(row: &Row) -> VersionMetadata {
+    let Json(features) = row.get("features");
+
+    VersionMetadata {
+        id: row.get::<_, i32>("id") as u32,
+        crate_name: row.get("name"),
+        num: Version::parse(row.get("version")).unwrap(),
+        checksum: row.get("cksum"),
+        yanked: row.get("yanked"),
+        features,
+        description: row.get("description"),
+        license: row.get("license"),
+        license_file: row.get("license_file"),
+        authors: row.get("authors"),
+        published_by: row
+            .get::<_, Option<String>>("published_by")
+            .map(|login| VersionPublisher { login }),
+        lib_links: row.get("links"),
+        rust_version: row.get("rust_version"),
+        downloads: row.get::<_, i64>("downloads") as u64,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Render a crate's sparse entry from the normalized tables, and store it for serving.
+///
+/// This must be run as part of any transaction which changes the contents of a crate's entry.
+///
+/// Returns whether the stored entry changed.
+async fn materialize_sparse_entry(
+    transaction: &Transaction<'_>,
+    crate_id: i32,
+    crate_name: &str,
+) -> anyhow::Result<bool> {
+    let (build_statement, upsert_statement) = tokio::try_join!(
+        transaction.prepare_cached(include_str!("../sql/sparse-index/build-entry.sql")),
+        transaction.prepare_cached(include_str!("../sql/sparse-index/upsert-entry.sql")),
+    )
+    .context("Failed to prepare sparse entry materialization statements")?;
+
+    // features and dependencies are aggregated per version, so this is a single query
+    let rows = transaction
+        .query(&build_statement, &[&crate_id])
+        .await
+        .context("Failed to query sparse entry")?;
+
+    let mut versions = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let Json(features) = row.get("features");
+        let Json(features2): Json<HashMap<String, Vec<String>>> = row.get("features2");
+        let Json(deps) = row.get("deps");
+
+        versions.push(CrateVersion {
+            name: crate_name.to_string(),
+            vers: Version::parse(row.get("version"))
+                .context("Failed to parse crate version in db")?,
+            deps,
+            cksum: row.get("cksum"),
+            features,
+            yanked: row.get("yanked"),
+            links: row.get("links"),
+            // the schema version only needs to be raised for entries which use `features2`
+            v: if features2.is_empty() { 1 } else { 2 },
+            features2,
+            rust_version: row.get("rust_version"),
+        });
     }
+
+    versions.sort_unstable_by(|a, b| a.vers.cmp(&b.vers));
+
+    let entry = render_sparse_entry(&versions).context("Failed to render sparse entry")?;
+
+    let changed = transaction
+        .execute(&upsert_statement, &[&crate_name, &crate_id, &entry])
+        .await
+        .context("Failed to store sparse entry")?;
+
+    Ok(changed > 0)
 }
 
 fn search_row_to_entry(row: &Row) -> SearchResultsEntry {
@@ -546,14 +1252,14 @@ fn search_row_to_entry(row: &Row) -> SearchResultsEntry {
     // we should never receive 0 versions from our query
     let max_version = versions
         .iter()
-        .map(|s| Version::parse(&s).unwrap())
+        .map(|s| Version::parse(s).unwrap())
         .max()
         .unwrap();
 
     SearchResultsEntry {
         name: row.get("name"),
         max_version,
-        description: row.try_get("description").unwrap_or(String::new()),
+        description: row.try_get("description").unwrap_or_default(),
         homepage: row.get("homepage"),
         repository: row.get("repository"),
         documentation: row.get("documentation"),
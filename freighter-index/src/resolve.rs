@@ -0,0 +1,612 @@
+//! Resolution of the transitive dependencies of crate versions from their index entries.
+//!
+//! Dependencies are resolved to the highest matching version which isn't yanked, as cargo would
+//! when generating a new lockfile, and features are unified per package version.
+//! Optional dependencies are only followed when enabled by a feature, including through the
+//! `dep:name`, `name/feature` and `name?/feature` syntaxes.
+//!
+//! Dev-dependencies are not followed, and dependencies from other registries are recorded as
+//! unresolved rather than followed.
+//! Platform-specific dependencies are followed regardless of their target, so the result may
+//! contain more than is built for any single platform.
+
+use crate::{CrateVersion, DependencyKind, GraphDependency, PackageId, UnresolvedDependency};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::future::Future;
+
+/// A package version resolved by a [`Resolver`].
+pub struct ResolvedPackage {
+    pub version: CrateVersion,
+    /// The features enabled on the package.
+    pub features: BTreeSet<String>,
+    /// The resolved dependencies of the package.
+    pub dependencies: Vec<GraphDependency>,
+    /// Enabled dependencies on crates from other registries.
+    pub unresolved: Vec<UnresolvedDependency>,
+}
+
+struct Node {
+    version: CrateVersion,
+    features: BTreeSet<String>,
+    /// Enabled dependencies, by the name they are given in the manifest.
+    enabled: BTreeSet<String>,
+    /// Features requested on dependencies, by the name they are given in the manifest, which
+    /// apply once the dependency is enabled.
+    dependency_features: HashMap<String, BTreeSet<String>>,
+    edges: Vec<Edge>,
+    unresolved: Vec<UnresolvedDependency>,
+}
+
+struct Edge {
+    /// Name of the dependency in the manifest.
+    name: String,
+    package: PackageId,
+    dependency: GraphDependency,
+}
+
+enum Activation {
+    /// Enable a feature of a package.
+    Feature(PackageId, String),
+    /// Enable a dependency of a package, by the name it is given in the manifest.
+    Dependency(PackageId, String),
+    /// Enable a feature of a dependency of a package.
+    ///
+    /// Unless weak, this also enables the dependency.
+    DependencyFeature {
+        package: PackageId,
+        dependency: String,
+        feature: String,
+        weak: bool,
+    },
+}
+
+/// Resolver of the dependencies of a set of root package versions.
+///
+/// `fetch_entry` is used to look up the versions of a crate, and is called at most once per crate.
+pub struct Resolver<F> {
+    fetch_entry: F,
+    entries: HashMap<String, Vec<CrateVersion>>,
+    nodes: BTreeMap<PackageId, Node>,
+    queue: VecDeque<Activation>,
+}
+
+impl<F, Fut> Resolver<F>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<CrateVersion>>>,
+{
+    pub fn new(fetch_entry: F) -> Self {
+        Self {
+            fetch_entry,
+            entries: HashMap::new(),
+            nodes: BTreeMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Get the versions of a crate, looking them up if they haven't been already.
+    pub async fn get_entry(&mut self, crate_name: &str) -> anyhow::Result<&[CrateVersion]> {
+        if !self.entries.contains_key(crate_name) {
+            let versions = (self.fetch_entry)(crate_name.to_string()).await?;
+
+            self.entries.insert(crate_name.to_string(), versions);
+        }
+
+        Ok(&self.entries[crate_name])
+    }
+
+    /// Add a package version to resolve, enabling each value of its feature table in `features`.
+    pub fn add_root(&mut self, version: CrateVersion, features: Vec<String>) -> PackageId {
+        let id = self.add_node(version);
+
+        for feature in features {
+            self.push_value(&id, feature);
+        }
+
+        id
+    }
+
+    /// Resolve the dependencies of every root, returning every package version they need.
+    pub async fn resolve(mut self) -> anyhow::Result<BTreeMap<PackageId, ResolvedPackage>> {
+        while let Some(activation) = self.queue.pop_front() {
+            match activation {
+                Activation::Feature(package, feature) => self.enable_feature(&package, feature),
+                Activation::Dependency(package, dependency) => {
+                    self.enable_dependency(&package, dependency).await?
+                }
+                Activation::DependencyFeature {
+                    package,
+                    dependency,
+                    feature,
+                    weak,
+                } => {
+                    let node = self.nodes.get_mut(&package).unwrap();
+
+                    node.dependency_features
+                        .entry(dependency.clone())
+                        .or_default()
+                        .insert(feature.clone());
+
+                    if node.enabled.contains(&dependency) {
+                        for edge in node.edges.iter().filter(|edge| edge.name == dependency) {
+                            self.queue.push_back(Activation::Feature(
+                                edge.package.clone(),
+                                feature.clone(),
+                            ));
+                        }
+                    } else if !weak {
+                        // this also enables the feature of the same name, if there is one
+                        let activation = if implicit_features(&node.version).contains(&dependency) {
+                            Activation::Feature(package, dependency)
+                        } else {
+                            Activation::Dependency(package, dependency)
+                        };
+
+                        self.queue.push_back(activation);
+                    }
+                }
+            }
+        }
+
+        Ok(self
+            .nodes
+            .into_iter()
+            .map(|(id, node)| {
+                let package = ResolvedPackage {
+                    version: node.version,
+                    features: node.features,
+                    dependencies: node.edges.into_iter().map(|edge| edge.dependency).collect(),
+                    unresolved: node.unresolved,
+                };
+
+                (id, package)
+            })
+            .collect())
+    }
+
+    fn add_node(&mut self, version: CrateVersion) -> PackageId {
+        let id = PackageId {
+            name: version.name.clone(),
+            version: version.vers.clone(),
+        };
+
+        if self.nodes.contains_key(&id) {
+            return id;
+        }
+
+        for dependency in &version.deps {
+            if !dependency.optional {
+                self.queue
+                    .push_back(Activation::Dependency(id.clone(), dependency.name.clone()));
+            }
+        }
+
+        self.nodes.insert(
+            id.clone(),
+            Node {
+                version,
+                features: BTreeSet::new(),
+                enabled: BTreeSet::new(),
+                dependency_features: HashMap::new(),
+                edges: Vec::new(),
+                unresolved: Vec::new(),
+            },
+        );
+
+        id
+    }
+
+    /// Queue the activations for a value in a package's feature table.
+    fn push_value(&mut self, package: &PackageId, value: String) {
+        let activation = if let Some(dependency) = value.strip_prefix("dep:") {
+            Activation::Dependency(package.clone(), dependency.to_string())
+        } else if let Some((dependency, feature)) = value.split_once('/') {
+            let (dependency, weak) = match dependency.strip_suffix('?') {
+                Some(dependency) => (dependency, true),
+                None => (dependency, false),
+            };
+
+            Activation::DependencyFeature {
+                package: package.clone(),
+                dependency: dependency.to_string(),
+                feature: feature.to_string(),
+                weak,
+            }
+        } else {
+            Activation::Feature(package.clone(), value)
+        };
+
+        self.queue.push_back(activation);
+    }
+
+    fn enable_feature(&mut self, package: &PackageId, feature: String) {
+        let node = self.nodes.get_mut(package).unwrap();
+
+        if node.features.contains(&feature) {
+            return;
+        }
+
+        let values = feature_map(&node.version).get(&feature).cloned().cloned();
+
+        match values {
+            Some(values) => {
+                node.features.insert(feature);
+
+                for value in values {
+                    self.push_value(package, value);
+                }
+            }
+            // optional dependencies which aren't referred to with `dep:` are features of their own
+            None if implicit_features(&node.version).contains(&feature) => {
+                node.features.insert(feature.clone());
+
+                self.queue
+                    .push_back(Activation::Dependency(package.clone(), feature));
+            }
+            // unknown features, such as `default` for crates without default features, are ignored
+            None => {}
+        }
+    }
+
+    async fn enable_dependency(
+        &mut self,
+        package: &PackageId,
+        dependency: String,
+    ) -> anyhow::Result<()> {
+        let node = self.nodes.get_mut(package).unwrap();
+
+        if !node.enabled.insert(dependency.clone()) {
+            return Ok(());
+        }
+
+        let requested: Vec<String> = node
+            .dependency_features
+            .get(&dependency)
+            .map(|features| features.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let declared: Vec<_> = node
+            .version
+            .deps
+            .iter()
+            .filter(|dep| dep.name == dependency && !matches!(dep.kind, DependencyKind::Dev))
+            .cloned()
+            .collect();
+
+        for declared in declared {
+            // in the index, renamed dependencies are listed under their new name
+            let crate_name = declared.package.as_ref().unwrap_or(&declared.name);
+
+            if let Some(registry) = declared.registry {
+                self.nodes
+                    .get_mut(package)
+                    .unwrap()
+                    .unresolved
+                    .push(UnresolvedDependency {
+                        name: crate_name.clone(),
+                        req: declared.req,
+                        registry,
+                        kind: declared.kind,
+                        target: declared.target,
+                    });
+
+                continue;
+            }
+
+            let resolved = self
+                .get_entry(crate_name)
+                .await?
+                .iter()
+                .filter(|candidate| !candidate.yanked && declared.req.matches(&candidate.vers))
+                .max_by(|a, b| a.vers.cmp(&b.vers))
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No version of {crate_name} matches {} required by {} {}",
+                        declared.req,
+                        package.name,
+                        package.version
+                    )
+                })?;
+
+            let id = self.add_node(resolved);
+
+            if declared.default_features {
+                self.queue
+                    .push_back(Activation::Feature(id.clone(), "default".to_string()));
+            }
+
+            for feature in declared.features.iter().chain(&requested) {
+                self.push_value(&id, feature.clone());
+            }
+
+            self.nodes.get_mut(package).unwrap().edges.push(Edge {
+                name: dependency.clone(),
+                package: id.clone(),
+                dependency: GraphDependency {
+                    name: id.name,
+                    version: id.version,
+                    req: declared.req,
+                    kind: declared.kind,
+                    target: declared.target,
+                },
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The feature table of a version, including features using the extended syntax.
+fn feature_map(version: &CrateVersion) -> HashMap<&String, &Vec<String>> {
+    version.features.iter().chain(&version.features2).collect()
+}
+
+/// Every feature of a version, including implicit ones.
+pub fn all_features(version: &CrateVersion) -> Vec<String> {
+    let mut features: Vec<String> = feature_map(version).into_keys().cloned().collect();
+
+    features.extend(implicit_features(version));
+
+    features
+}
+
+/// The optional dependencies of a version which act as features, as they aren't referred to with
+/// `dep:` anywhere in its feature table.
+fn implicit_features(version: &CrateVersion) -> Vec<String> {
+    let features = feature_map(version);
+
+    version
+        .deps
+        .iter()
+        .filter(|dep| dep.optional && !matches!(dep.kind, DependencyKind::Dev))
+        .filter(|dep| {
+            let explicit = format!("dep:{}", dep.name);
+
+            !features.values().any(|values| values.contains(&explicit))
+        })
+        .map(|dep| dep.name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// A dependency in an index entry line.
+    fn dep(name: &str, req: &str, kind: &str) -> Value {
+        json!({
+            "name": name,
+            "req": req,
+            "features": [],
+            "optional": false,
+            "default_features": true,
+            "target": null,
+            "kind": kind,
+            "registry": null,
+            "package": null,
+        })
+    }
+
+    /// An index entry line with the given dependencies and feature table.
+    fn version(
+        name: &str,
+        vers: &str,
+        yanked: bool,
+        deps: &[Value],
+        features: Value,
+    ) -> CrateVersion {
+        serde_json::from_value(json!({
+            "name": name,
+            "vers": vers,
+            "deps": deps,
+            "cksum": "0".repeat(64),
+            "features": features,
+            "yanked": yanked,
+            "links": null,
+        }))
+        .unwrap()
+    }
+
+    /// A stand-in registry, which resolves crates from `versions` and records each lookup.
+    struct StandIn {
+        versions: Vec<CrateVersion>,
+        lookups: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl StandIn {
+        fn new(versions: Vec<CrateVersion>) -> Self {
+            Self {
+                versions,
+                lookups: Default::default(),
+            }
+        }
+
+        async fn fetch(&self, crate_name: String) -> anyhow::Result<Vec<CrateVersion>> {
+            self.lookups.lock().unwrap().push(crate_name.clone());
+
+            let versions: Vec<_> = self
+                .versions
+                .iter()
+                .filter(|version| version.name == crate_name)
+                .cloned()
+                .collect();
+
+            if versions.is_empty() {
+                anyhow::bail!("No crate named {crate_name}");
+            }
+
+            Ok(versions)
+        }
+
+        /// Resolve the given versions with their default features.
+        async fn resolve(
+            &self,
+            roots: &[(&str, &str)],
+        ) -> anyhow::Result<BTreeMap<PackageId, ResolvedPackage>> {
+            let mut resolver = Resolver::new(|crate_name| self.fetch(crate_name));
+
+            for (name, vers) in roots {
+                let version = resolver
+                    .get_entry(name)
+                    .await?
+                    .iter()
+                    .find(|version| version.vers.to_string() == *vers)
+                    .cloned()
+                    .unwrap();
+
+                resolver.add_root(version, vec!["default".to_string()]);
+            }
+
+            resolver.resolve().await
+        }
+    }
+
+    fn resolved(packages: &BTreeMap<PackageId, ResolvedPackage>) -> Vec<String> {
+        packages
+            .keys()
+            .map(|id| format!("{}@{}", id.name, id.version))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn resolves_highest_unyanked_dependency_versions() {
+        let registry = StandIn::new(vec![
+            version(
+                "app",
+                "1.0.0",
+                false,
+                &[dep("lib", "^1", "normal")],
+                json!({}),
+            ),
+            version(
+                "app",
+                "2.0.0",
+                false,
+                &[dep("lib", "^1.1", "normal")],
+                json!({}),
+            ),
+            version(
+                "lib",
+                "1.0.0",
+                false,
+                &[dep("leaf", "^0.1", "build")],
+                json!({}),
+            ),
+            version(
+                "lib",
+                "1.1.0",
+                false,
+                &[dep("leaf", "^0.1", "build")],
+                json!({}),
+            ),
+            version("lib", "1.2.0", true, &[], json!({})),
+            version("lib", "2.0.0", false, &[], json!({})),
+            version(
+                "leaf",
+                "0.1.0",
+                false,
+                &[dep("test-helper", "^1", "dev")],
+                json!({}),
+            ),
+        ]);
+
+        let packages = registry
+            .resolve(&[("app", "1.0.0"), ("app", "2.0.0")])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resolved(&packages),
+            ["app@1.0.0", "app@2.0.0", "leaf@0.1.0", "lib@1.1.0"]
+        );
+
+        // each crate is only looked up once, and dev-dependencies aren't followed
+        let mut lookups = registry.lookups.lock().unwrap().clone();
+        lookups.sort();
+        assert_eq!(lookups, ["app", "leaf", "lib"]);
+    }
+
+    #[tokio::test]
+    async fn optional_dependencies_are_followed_when_enabled() {
+        let mut optional = dep("opt", "^1", "normal");
+        optional["optional"] = json!(true);
+
+        let mut enabled = dep("lib", "^1", "normal");
+        enabled["features"] = json!(["extra"]);
+
+        let registry = StandIn::new(vec![
+            version("app", "1.0.0", false, &[enabled], json!({})),
+            version(
+                "lib",
+                "1.0.0",
+                false,
+                &[optional.clone()],
+                json!({ "extra": ["dep:opt"] }),
+            ),
+            version("other", "1.0.0", false, &[optional], json!({})),
+            version("opt", "1.0.0", false, &[], json!({})),
+        ]);
+
+        let packages = registry
+            .resolve(&[("app", "1.0.0"), ("other", "1.0.0")])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resolved(&packages),
+            ["app@1.0.0", "lib@1.0.0", "opt@1.0.0", "other@1.0.0"]
+        );
+
+        let other = &packages[&PackageId {
+            name: "other".to_string(),
+            version: "1.0.0".parse().unwrap(),
+        }];
+
+        assert!(other.dependencies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dependencies_from_other_registries_are_unresolved() {
+        let mut foreign = dep("elsewhere", "^2", "normal");
+        foreign["registry"] = json!("https://example.com/index");
+
+        let registry = StandIn::new(vec![version("app", "1.0.0", false, &[foreign], json!({}))]);
+
+        let packages = registry.resolve(&[("app", "1.0.0")]).await.unwrap();
+
+        assert_eq!(resolved(&packages), ["app@1.0.0"]);
+
+        let app = packages.values().next().unwrap();
+
+        assert!(app.dependencies.is_empty());
+        assert_eq!(app.unresolved.len(), 1);
+        assert_eq!(app.unresolved[0].name, "elsewhere");
+        assert_eq!(app.unresolved[0].registry, "https://example.com/index");
+    }
+
+    #[tokio::test]
+    async fn unresolvable_dependency_is_an_error() {
+        let registry = StandIn::new(vec![
+            version(
+                "app",
+                "1.0.0",
+                false,
+                &[dep("lib", "^1", "normal")],
+                json!({}),
+            ),
+            version("lib", "1.0.0", true, &[], json!({})),
+        ]);
+
+        let Err(error) = registry.resolve(&[("app", "1.0.0")]).await else {
+            panic!("Resolved a yanked dependency");
+        };
+
+        assert!(
+            error.to_string().contains("required by app 1.0.0"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use crate::CrateVersion;
+
+/// Get the path of a crate's entry relative to the root of a sparse index.
+///
+/// This is the crate's [prefix](sparse_entry_prefix) followed by its name, and is always
+/// lowercase.
+pub fn sparse_entry_path(crate_name: &str) -> String {
+    format!("{}/{crate_name}", sparse_entry_prefix(crate_name)).to_lowercase()
+}
+
+/// Get the directory of a crate's entry relative to the root of a sparse index.
+///
+/// This follows the layout used by cargo: crates with one, two, or three character names are
+/// placed in the `1`, `2`, and `3/{first character}` directories respectively, and all other
+/// crates are placed in `{first two characters}/{next two characters}`.
+/// The case of the crate name is preserved.
+pub fn sparse_entry_prefix(crate_name: &str) -> String {
+    let chars: Vec<char> = crate_name.chars().collect();
+
+    match chars.len() {
+        0 | 1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", chars[0]),
+        _ => format!("{}{}/{}{}", chars[0], chars[1], chars[2], chars[3]),
+    }
+}
+
+/// Whether the values of a feature use syntax which older versions of cargo can't parse, namely
+/// namespaced (`dep:`) or weak (`?/`) dependency features.
+///
+/// Such features belong in the `features2` field of a sparse entry, rather than `features`.
+pub fn is_extended_feature(values: &[String]) -> bool {
+    values
+        .iter()
+        .any(|value| value.starts_with("dep:") || value.contains("?/"))
+}
+
+/// Render a crate's sparse entry in the JSON lines format served to cargo.
+pub fn render_sparse_entry(versions: &[CrateVersion]) -> serde_json::Result<String> {
+    let mut entry = String::new();
+
+    for version in versions {
+        entry.push_str(&serde_json::to_string(version)?);
+        entry.push('\n');
+    }
+
+    Ok(entry)
+}
+
+/// Parse a crate's sparse entry from the JSON lines format served to cargo.
+pub fn parse_sparse_entry(entry: &str) -> serde_json::Result<Vec<CrateVersion>> {
+    entry.lines().map(serde_json::from_str).collect()
+}
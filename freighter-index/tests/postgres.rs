@@ -0,0 +1,85 @@
+//! Tests of the Postgres index, against the database configured as described in
+//! [`freighter_test_support::index_db_config`].
+
+#![cfg(feature = "postgresql-backend")]
+
+use freighter_index::postgres_client::PgIndexProvider;
+use freighter_index::{IndexProvider, ListQuery, Publish, SearchFilters, SearchResults};
+use freighter_test_support::{index_db_config, unique_name};
+use serde_json::{json, Value};
+
+fn test_index() -> PgIndexProvider {
+    PgIndexProvider::new(index_db_config()).unwrap()
+}
+
+async fn publish(index: &PgIndexProvider, name: &str, keywords: &[&str], categories: &[&str]) {
+    let version: Publish = serde_json::from_value(json!({
+        "name": name,
+        "vers": "1.0.0",
+        "deps": [],
+        "features": {},
+        "description": "A crate",
+        "documentation": null,
+        "homepage": null,
+        "readme": null,
+        "readme_file": null,
+        "keywords": keywords,
+        "categories": categories,
+        "license": "MIT",
+        "license_file": null,
+        "repository": null,
+        "badges": null,
+        "links": null,
+    }))
+    .unwrap();
+
+    index
+        .publish(&version, &"0".repeat(64), None, Box::pin(async { Ok(()) }))
+        .await
+        .unwrap();
+}
+
+/// The keywords and categories of a crate in search results, as JSON.
+fn keywords_and_categories(results: &SearchResults, name: &str) -> (Value, Value) {
+    let results = serde_json::to_value(&results.crates).unwrap();
+
+    let entry = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["name"] == name)
+        .unwrap_or_else(|| panic!("{name} is missing from the results"));
+
+    (entry["keywords"].clone(), entry["categories"].clone())
+}
+
+#[tokio::test]
+#[ignore = "needs FREIGHTER_TEST_INDEX_DB"]
+async fn missing_keywords_and_categories_are_empty() {
+    let index = test_index();
+
+    let with = unique_name("with-metadata");
+    let without = unique_name("without-metadata");
+
+    publish(&index, &with, &["parsing"], &["encoding"]).await;
+    publish(&index, &without, &[], &[]).await;
+
+    let filters = SearchFilters {
+        crates: Some(vec![with.clone(), without.clone()]),
+        ..Default::default()
+    };
+
+    let search = index.search("", &filters, 10).await.unwrap();
+    let list = index.list(&ListQuery::default()).await.unwrap();
+
+    for results in [&search, &list] {
+        assert_eq!(
+            keywords_and_categories(results, &with),
+            (json!(["parsing"]), json!(["encoding"]))
+        );
+        assert_eq!(
+            keywords_and_categories(results, &without),
+            (json!([]), json!([]))
+        );
+    }
+}
@@ -1,22 +1,34 @@
-use crate::ServiceState;
+use crate::graph::FeatureSelection;
+use crate::source::{SourceFile, MAX_SOURCE_FILE_SIZE};
+use crate::{diff, docs, graph, readme, sbom, ServiceState};
 use anyhow::Context;
 use axum::body::Bytes;
-use axum::extract::{Path, Query, State};
-use axum::http::header::AUTHORIZATION;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
 use axum::http::{HeaderMap, StatusCode};
-use axum::response::Html;
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::{delete, get, post, put};
 use axum::{Form, Json, Router};
 use freighter_auth::AuthProvider;
 use freighter_index::{
-    AuthForm, CompletedPublication, IndexProvider, ListQuery, Publish, SearchQuery, SearchResults,
-    SearchResultsEntry,
+    AuthForm, CompletedPublication, CrateDiff, CrateFilesResponse, CrateResponse,
+    DocsUploadResponse, DownloadsResponse, IndexError, IndexProvider, ListQuery, PackageId,
+    Publish, ReverseDependencies, ReverseDependenciesQuery, SearchFilters, SearchQuery,
+    SearchResults, VersionResponse, VersionsMeta, VersionsResponse,
 };
-use freighter_storage::StorageProvider;
+use freighter_storage::{StorageError, StorageProvider};
 use semver::Version;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+/// Number of items per page of search results and listings, if not requested otherwise.
+const DEFAULT_PER_PAGE: usize = 10;
+/// Maximum number of items per page of search results and listings.
+const MAX_PER_PAGE: usize = 100;
+/// Number of days of daily download counts to serve, like crates.io.
+const DOWNLOADS_HISTORY_DAYS: i64 = 90;
 
 #[non_exhaustive]
 #[derive(Deserialize)]
@@ -24,6 +36,48 @@ pub struct OwnerListChange {
     pub users: Vec<String>,
 }
 
+#[derive(Deserialize)]
+pub struct ReadmeQuery {
+    #[serde(default)]
+    pub format: ReadmeFormat,
+}
+
+#[derive(Deserialize)]
+pub struct SbomQuery {
+    /// Comma-separated features to enable on the crate.
+    pub features: Option<String>,
+    #[serde(default)]
+    pub all_features: bool,
+    #[serde(default)]
+    pub no_default_features: bool,
+    #[serde(default)]
+    pub format: SbomFormat,
+}
+
+/// The format to serve a dependency graph in.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    /// The [`DependencyGraph`](freighter_index::DependencyGraph) itself.
+    #[default]
+    Json,
+    /// A CycloneDX 1.5 document.
+    Cyclonedx,
+    /// An SPDX 2.3 document.
+    Spdx,
+}
+
+/// The format to serve a README in.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadmeFormat {
+    /// Rendered and sanitized HTML.
+    #[default]
+    Html,
+    /// The README as it was published.
+    Raw,
+}
+
 pub fn api_router<I, S, A>() -> Router<Arc<ServiceState<I, S, A>>>
 where
     I: IndexProvider + Send + Sync + 'static,
@@ -41,6 +95,23 @@ where
         .route("/account/token", post(login))
         .route("/", get(search))
         .route("/all", get(list))
+        .route("/:crate_name", get(get_crate))
+        .route("/:crate_name/versions", get(list_versions))
+        .route("/:crate_name/downloads", get(get_downloads))
+        .route(
+            "/:crate_name/reverse_dependencies",
+            get(list_reverse_dependencies),
+        )
+        .route("/:crate_name/:version", get(get_version))
+        .route("/:crate_name/:version/readme", get(get_readme))
+        .route("/:crate_name/:version/files", get(list_files))
+        .route("/:crate_name/:version/files/*path", get(get_file))
+        .route("/:crate_name/:version/diff/:to", get(diff_versions))
+        .route(
+            "/:crate_name/:version/docs",
+            put(upload_docs).layer(DefaultBodyLimit::max(docs::MAX_DOCS_ARCHIVE_SIZE)),
+        )
+        .route("/:crate_name/:version/sbom", get(get_sbom))
         .fallback(handle_api_fallback)
 }
 
@@ -52,7 +123,7 @@ async fn publish<I, S, A>(
 where
     I: IndexProvider + Send + Sync,
     S: StorageProvider + Send + Sync + Clone + 'static,
-    A: AuthProvider,
+    A: AuthProvider + Sync,
 {
     if body.len() <= 4 {
         return Err(StatusCode::BAD_REQUEST.into());
@@ -80,7 +151,8 @@ where
 
     let crate_bytes = body.split_to(crate_len);
 
-    let json: Publish = serde_json::from_slice(&json_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut json: Publish =
+        serde_json::from_slice(&json_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
 
     let auth = headers
         .get(AUTHORIZATION)
@@ -90,8 +162,32 @@ where
 
     state.auth.publish(auth, &json.name).await?;
 
+    let publisher = state.auth.get_username(auth).await?;
+
     let hash = format!("{:x}", Sha256::digest(&crate_bytes));
 
+    // the README in the crate file takes precedence over the one sent with the metadata
+    if let Some(readme_file) = json.readme_file.clone() {
+        let crate_bytes = crate_bytes.clone();
+
+        let readme =
+            tokio::task::spawn_blocking(move || readme::extract_readme(&readme_file, &crate_bytes))
+                .await
+                .context("README extraction task failed")
+                .and_then(|readme| readme);
+
+        match readme {
+            Ok(Some(readme)) => json.readme = Some(readme),
+            Ok(None) => {}
+            Err(error) => tracing::warn!(
+                ?error,
+                crate_name = json.name,
+                version = %json.vers,
+                "Failed to extract readme from crate"
+            ),
+        }
+    }
+
     let name = json.name.clone();
     let version = json.vers.to_string();
     let storage = state.storage.clone();
@@ -101,6 +197,7 @@ where
         .publish(
             &json,
             &hash,
+            publisher.as_deref(),
             Box::pin(async move {
                 storage
                     .put_crate(&name, &version, &crate_bytes)
@@ -109,7 +206,7 @@ where
             }),
         )
         .await
-        .map(|x| Json(x))?;
+        .map(Json)?;
 
     Ok(resp)
 }
@@ -271,9 +368,24 @@ where
 
     state.auth.auth_view_full_index(token).await?;
 
+    let crates = match &query.owner {
+        Some(owner) => Some(state.auth.list_owned_crates(owner).await?),
+        None => None,
+    };
+
+    let filters = SearchFilters {
+        keyword: query.keyword,
+        category: query.category,
+        crates,
+    };
+
     let search_results = state
         .index
-        .search(&query.q, query.per_page.map(|x| x.max(100)).unwrap_or(10))
+        .search(
+            &query.q,
+            &filters,
+            query.per_page.unwrap_or(DEFAULT_PER_PAGE).min(MAX_PER_PAGE),
+        )
         .await?;
 
     Ok(Json(search_results))
@@ -282,8 +394,8 @@ where
 async fn list<I, S, A>(
     headers: HeaderMap,
     State(state): State<Arc<ServiceState<I, S, A>>>,
-    Query(query): Query<ListQuery>,
-) -> axum::response::Result<Json<Vec<SearchResultsEntry>>>
+    Query(mut query): Query<ListQuery>,
+) -> axum::response::Result<Json<SearchResults>>
 where
     I: IndexProvider,
     A: AuthProvider + Sync,
@@ -295,11 +407,376 @@ where
 
     state.auth.auth_view_full_index(token).await?;
 
+    query.per_page = Some(query.per_page.unwrap_or(DEFAULT_PER_PAGE).min(MAX_PER_PAGE));
+
+    // pages far enough out have offsets which can't be represented
+    query.offset().ok_or(StatusCode::BAD_REQUEST)?;
+
     let search_results = state.index.list(&query).await?;
 
     Ok(Json(search_results))
 }
 
+async fn get_crate<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path(name): Path<String>,
+) -> axum::response::Result<Json<CrateResponse>>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_index_fetch(token, &name).await?;
+
+    let crate_response = state.index.get_crate(&name).await?;
+
+    Ok(Json(crate_response))
+}
+
+async fn list_versions<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path(name): Path<String>,
+) -> axum::response::Result<Json<VersionsResponse>>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_index_fetch(token, &name).await?;
+
+    let versions = state.index.list_versions(&name).await?;
+
+    Ok(Json(VersionsResponse {
+        meta: VersionsMeta {
+            total: versions.len(),
+        },
+        versions,
+    }))
+}
+
+async fn get_downloads<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path(name): Path<String>,
+) -> axum::response::Result<Json<DownloadsResponse>>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_index_fetch(token, &name).await?;
+
+    let since = OffsetDateTime::now_utc().date() - Duration::days(DOWNLOADS_HISTORY_DAYS);
+
+    let version_downloads = state.index.get_version_downloads(&name, since).await?;
+
+    Ok(Json(DownloadsResponse { version_downloads }))
+}
+
+async fn list_reverse_dependencies<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path(name): Path<String>,
+    Query(mut query): Query<ReverseDependenciesQuery>,
+) -> axum::response::Result<Json<ReverseDependencies>>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    // this reveals crates other than the one asked about
+    state.auth.auth_view_full_index(token).await?;
+
+    query.per_page = Some(query.per_page.unwrap_or(DEFAULT_PER_PAGE).min(MAX_PER_PAGE));
+
+    // pages far enough out have offsets which can't be represented
+    query.offset().ok_or(StatusCode::BAD_REQUEST)?;
+
+    let dependencies = state.index.list_reverse_dependencies(&name, &query).await?;
+
+    Ok(Json(dependencies))
+}
+
+async fn get_version<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, Version)>,
+) -> axum::response::Result<Json<VersionResponse>>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_index_fetch(token, &name).await?;
+
+    let version = state.index.get_version_metadata(&name, &version).await?;
+
+    Ok(Json(VersionResponse { version }))
+}
+
+async fn get_sbom<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, Version)>,
+    Query(query): Query<SbomQuery>,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    // this reveals every crate in the graph
+    state.auth.auth_view_full_index(token).await?;
+
+    let selection = FeatureSelection {
+        features: query
+            .features
+            .iter()
+            .flat_map(|features| features.split(','))
+            .map(str::trim)
+            .filter(|feature| !feature.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+        all_features: query.all_features,
+        default_features: !query.no_default_features,
+    };
+
+    let root = PackageId { name, version };
+
+    let graph = graph::resolve_graph(&state.index, &root, &selection).await?;
+
+    let resp = match query.format {
+        SbomFormat::Json => Json(graph).into_response(),
+        SbomFormat::Cyclonedx => (
+            [(CONTENT_TYPE, "application/vnd.cyclonedx+json")],
+            Json(sbom::cyclonedx(&graph, &state.config)),
+        )
+            .into_response(),
+        SbomFormat::Spdx => (
+            [(CONTENT_TYPE, "application/spdx+json")],
+            Json(sbom::spdx(&graph, &state.config)),
+        )
+            .into_response(),
+    };
+
+    Ok(resp)
+}
+
+async fn get_readme<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, Version)>,
+    Query(query): Query<ReadmeQuery>,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_index_fetch(token, &name).await?;
+
+    let readme = state.index.get_readme(&name, &version).await?;
+
+    let resp = match query.format {
+        ReadmeFormat::Html => {
+            let rendered = tokio::task::spawn_blocking(move || readme::render_readme(&readme))
+                .await
+                .context("README rendering task failed")
+                .map_err(IndexError::from)?;
+
+            Html(rendered).into_response()
+        }
+        ReadmeFormat::Raw => {
+            let content_type = if readme::is_markdown(&readme) {
+                "text/markdown; charset=utf-8"
+            } else {
+                "text/plain; charset=utf-8"
+            };
+
+            ([(CONTENT_TYPE, content_type)], readme.contents).into_response()
+        }
+    };
+
+    Ok(resp)
+}
+
+async fn list_files<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, Version)>,
+) -> axum::response::Result<Json<CrateFilesResponse>>
+where
+    I: IndexProvider,
+    S: StorageProvider + Sync,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_crate_download(token, &name).await?;
+
+    state.index.confirm_existence(&name, &version).await?;
+
+    let files = state
+        .file_listings
+        .get(&state.storage, &name, &version)
+        .await?;
+
+    Ok(Json(CrateFilesResponse {
+        files: files.as_ref().clone(),
+    }))
+}
+
+async fn get_file<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version, path)): Path<(String, Version, String)>,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    S: StorageProvider + Sync,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_crate_download(token, &name).await?;
+
+    state.index.confirm_existence(&name, &version).await?;
+
+    let file = state
+        .file_listings
+        .read(
+            &state.storage,
+            &name,
+            &version,
+            path.trim_start_matches('/'),
+        )
+        .await?;
+
+    let contents = match file {
+        SourceFile::Contents(contents) => contents,
+        SourceFile::TooLarge(size) => {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "File is {size} bytes, which is more than the limit of {MAX_SOURCE_FILE_SIZE}"
+                ),
+            )
+                .into())
+        }
+    };
+
+    let content_type = if std::str::from_utf8(&contents).is_ok() {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        contents,
+    )
+        .into_response())
+}
+
+async fn diff_versions<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, from, to)): Path<(String, Version, Version)>,
+) -> axum::response::Result<Json<CrateDiff>>
+where
+    I: IndexProvider,
+    S: StorageProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_crate_download(token, &name).await?;
+
+    state.index.confirm_existence(&name, &from).await?;
+    state.index.confirm_existence(&name, &to).await?;
+
+    let from_bytes = state.storage.pull_crate(&name, &from.to_string()).await?;
+    let to_bytes = state.storage.pull_crate(&name, &to.to_string()).await?;
+
+    let diff =
+        tokio::task::spawn_blocking(move || diff::diff_crates(&from, &from_bytes, &to, &to_bytes))
+            .await
+            .context("Diff task failed")
+            .and_then(|diff| diff)
+            .context("Failed to diff crate versions")
+            .map_err(StorageError::from)?;
+
+    Ok(Json(diff))
+}
+
+async fn upload_docs<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, Version)>,
+    body: Bytes,
+) -> axum::response::Result<Json<DocsUploadResponse>>
+where
+    I: IndexProvider,
+    S: StorageProvider + Sync,
+    A: AuthProvider,
+{
+    let auth = headers
+        .get(AUTHORIZATION)
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_str()
+        .or(Err(StatusCode::BAD_REQUEST))?;
+
+    state.auth.auth_upload_docs(auth, &name).await?;
+
+    state.index.confirm_existence(&name, &version).await?;
+
+    let files = docs::upload_docs(&state.storage, &name, &version, body).await?;
+
+    Ok(Json(DocsUploadResponse { files }))
+}
+
 async fn handle_api_fallback() -> StatusCode {
     StatusCode::NOT_FOUND
 }
@@ -0,0 +1,86 @@
+//! Helpers for HTTP caching headers and conditional requests.
+
+use axum::http::header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use axum::http::{HeaderMap, HeaderValue};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Validators describing a particular version of a response body.
+pub(crate) struct Validators {
+    /// A quoted entity tag.
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Check whether the conditional headers of a request show that the client already has this
+    /// version of the response, in which case a `304 Not Modified` should be sent instead.
+    pub fn is_fresh(&self, request_headers: &HeaderMap) -> bool {
+        // as per RFC 9110, if-modified-since must be ignored if if-none-match is present
+        if let Some(if_none_match) = request_headers.get(IF_NONE_MATCH) {
+            return if_none_match
+                .to_str()
+                .map(|value| etag_matches(value, &self.etag))
+                .unwrap_or(false);
+        }
+
+        let if_modified_since = request_headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+
+        match (self.last_modified, if_modified_since) {
+            // http dates only have a resolution of one second
+            (Some(last_modified), Some(since)) => {
+                unix_seconds(last_modified) <= unix_seconds(since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Add the validators to a set of response headers.
+    pub fn apply(&self, response_headers: &mut HeaderMap) {
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            response_headers.insert(ETAG, etag);
+        }
+
+        if let Some(last_modified) = self.last_modified {
+            let value = httpdate::fmt_http_date(last_modified);
+
+            // http dates are always valid header values
+            response_headers.insert(LAST_MODIFIED, HeaderValue::from_str(&value).unwrap());
+        }
+    }
+}
+
+/// Add a configured `Cache-Control` header to a set of response headers.
+pub(crate) fn apply_cache_control(value: Option<&str>, response_headers: &mut HeaderMap) {
+    if let Some(value) = value {
+        match HeaderValue::from_str(value) {
+            Ok(value) => {
+                response_headers.insert(CACHE_CONTROL, value);
+            }
+            Err(error) => {
+                tracing::warn!(
+                    ?error,
+                    "Configured cache-control header is not a valid value"
+                );
+            }
+        }
+    }
+}
+
+/// Weak comparison of an `If-None-Match` header against an entity tag.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,145 @@
+//! Comparison of the files of two versions of a crate.
+//!
+//! Both `.crate` files are read in full, and the text files which differ are compared line by
+//! line to produce a unified diff in the format of `git diff`.
+//! Files which contain NUL bytes or aren't valid UTF-8 are treated as binary, and are only listed
+//! as changed.
+//!
+//! To bound the work done for a request, files larger than [`MAX_DIFF_FILE_SIZE`], and any files
+//! read after [`MAX_DIFF_TOTAL_SIZE`] bytes of a crate, are only compared by hash.
+//! Line diffs which take longer than [`DIFF_TIMEOUT`] in total fall back to less minimal diffs.
+
+use crate::source::MAX_SOURCE_FILE_SIZE;
+use crate::tarball::{self, FileContents};
+use anyhow::Context;
+use freighter_index::{CrateDiff, FileDiff, FileDiffStatus};
+use semver::Version;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Largest file which will be diffed.
+const MAX_DIFF_FILE_SIZE: u64 = MAX_SOURCE_FILE_SIZE;
+
+/// Largest total size of the files read from each crate.
+const MAX_DIFF_TOTAL_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Time after which line diffs are approximated.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Compare the files of two versions of a crate, given their `.crate` files.
+///
+/// This can take up to several seconds of CPU time, so it should be run on a blocking thread.
+pub(crate) fn diff_crates(
+    from: &Version,
+    from_bytes: &[u8],
+    to: &Version,
+    to_bytes: &[u8],
+) -> anyhow::Result<CrateDiff> {
+    let old = tarball::read_files(from_bytes, MAX_DIFF_FILE_SIZE, MAX_DIFF_TOTAL_SIZE)
+        .with_context(|| format!("Failed to read files of version {from}"))?;
+    let new = tarball::read_files(to_bytes, MAX_DIFF_FILE_SIZE, MAX_DIFF_TOTAL_SIZE)
+        .with_context(|| format!("Failed to read files of version {to}"))?;
+
+    let deadline = Instant::now() + DIFF_TIMEOUT;
+
+    let mut files = Vec::new();
+    let mut diff = String::new();
+
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    for path in paths {
+        let (old_file, new_file) = (old.get(path), new.get(path));
+
+        let status = match (old_file, new_file) {
+            (Some(old_file), Some(new_file)) if old_file.sha256 == new_file.sha256 => continue,
+            (Some(_), Some(_)) => FileDiffStatus::Modified,
+            (None, _) => FileDiffStatus::Added,
+            (_, None) => FileDiffStatus::Removed,
+        };
+
+        let too_large = [old_file, new_file]
+            .into_iter()
+            .flatten()
+            .any(|file| file.contents.is_none());
+
+        let mut file_diff = FileDiff {
+            path: path.clone(),
+            status,
+            binary: false,
+            too_large,
+            additions: 0,
+            deletions: 0,
+        };
+
+        if !too_large {
+            let old_name = match status {
+                FileDiffStatus::Added => "/dev/null".to_string(),
+                _ => format!("a/{path}"),
+            };
+            let new_name = match status {
+                FileDiffStatus::Removed => "/dev/null".to_string(),
+                _ => format!("b/{path}"),
+            };
+
+            // writing to a string can't fail
+            writeln!(diff, "diff --git a/{path} b/{path}").unwrap();
+
+            match (as_text(old_file), as_text(new_file)) {
+                (Some(old_text), Some(new_text)) => {
+                    let text_diff = TextDiff::configure()
+                        .deadline(deadline)
+                        .diff_lines(old_text, new_text);
+
+                    for change in text_diff.iter_all_changes() {
+                        match change.tag() {
+                            ChangeTag::Insert => file_diff.additions += 1,
+                            ChangeTag::Delete => file_diff.deletions += 1,
+                            ChangeTag::Equal => {}
+                        }
+                    }
+
+                    write!(
+                        diff,
+                        "{}",
+                        text_diff
+                            .unified_diff()
+                            .context_radius(3)
+                            .header(&old_name, &new_name)
+                    )
+                    .unwrap();
+                }
+                _ => {
+                    file_diff.binary = true;
+
+                    writeln!(diff, "Binary files {old_name} and {new_name} differ").unwrap();
+                }
+            }
+        }
+
+        files.push(file_diff);
+    }
+
+    Ok(CrateDiff {
+        from: from.clone(),
+        to: to.clone(),
+        files,
+        diff,
+    })
+}
+
+/// Get one side of a file as text, treating a missing file as empty.
+///
+/// Returns `None` if the file is binary.
+fn as_text(file: Option<&FileContents>) -> Option<&str> {
+    let Some(contents) = file.and_then(|file| file.contents.as_deref()) else {
+        return Some("");
+    };
+
+    if contents.contains(&0) {
+        return None;
+    }
+
+    std::str::from_utf8(contents).ok()
+}
@@ -0,0 +1,459 @@
+//! Hosting of rustdoc output for crate versions.
+//!
+//! Owners of a crate can upload the documentation of a version as a gzipped tarball of the
+//! contents of `target/doc`, such as one made by `tar -czf docs.tar.gz -C target/doc .`, with a
+//! `PUT` to `/api/v1/crates/{name}/{version}/docs`.
+//! Every file in the archive is stored through the storage provider, and served as static files
+//! under `/docs/{name}/{version}/`.
+//! Docs are served with a `Content-Security-Policy` which sandboxes them, since they are arbitrary
+//! HTML and JavaScript from crate owners.
+//! Uploading docs for a version again replaces the files with the same paths, but files which
+//! are not in the new archive are left in place.
+//!
+//! `latest` can be used in place of a version to be redirected to the docs of the highest stable
+//! version of a crate, as determined by [`CrateMetadata`](freighter_index::CrateMetadata).
+
+use crate::ServiceState;
+use anyhow::{bail, Context};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::header::{
+    AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use flate2::read::GzDecoder;
+use freighter_auth::AuthProvider;
+use freighter_index::IndexProvider;
+use freighter_storage::{StorageError, StorageProvider};
+use futures_util::{StreamExt, TryStreamExt};
+use semver::Version;
+use std::io::Read;
+use std::path::Component;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+/// Largest docs archive which can be uploaded.
+pub const MAX_DOCS_ARCHIVE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Largest total size of the files in a docs archive.
+pub const MAX_DOCS_UNPACKED_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Largest single file in a docs archive.
+pub const MAX_DOCS_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Largest total size of the files from a docs archive which are held in memory at once while
+/// they are stored.
+const MAX_DOCS_IN_FLIGHT_SIZE: u64 = 2 * MAX_DOCS_FILE_SIZE;
+
+/// Content security policy of served docs.
+///
+/// Docs are uploaded by crate owners and served from the same origin as the rest of the registry,
+/// so they are sandboxed into a unique origin, which keeps their scripts away from the registry's
+/// cookies and storage, while still letting rustdoc's search and settings work.
+const DOCS_CONTENT_SECURITY_POLICY: &str = "sandbox allow-scripts allow-forms allow-popups";
+
+/// Number of files uploaded to the storage provider at once.
+const UPLOAD_CONCURRENCY: usize = 16;
+
+pub fn docs_router<I, S, A>() -> Router<Arc<ServiceState<I, S, A>>>
+where
+    I: IndexProvider + Send + Sync + 'static,
+    S: StorageProvider + Send + Sync + 'static,
+    A: AuthProvider + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/:name/:version", get(redirect_to_root))
+        .route("/:name/:version/", get(serve_root))
+        .route("/:name/:version/*path", get(serve_file))
+        .fallback(handle_docs_fallback)
+}
+
+/// Unpack and store a docs archive for a crate version.
+///
+/// The archive is checked in full before anything is stored, and then unpacked on a blocking
+/// thread while its files are stored, keeping at most [`MAX_DOCS_IN_FLIGHT_SIZE`] bytes of them in
+/// memory.
+///
+/// Returns the number of files stored.
+pub(crate) async fn upload_docs<S>(
+    storage: &S,
+    name: &str,
+    version: &Version,
+    archive: Bytes,
+) -> axum::response::Result<usize>
+where
+    S: StorageProvider + Sync,
+{
+    let in_flight = Arc::new(Semaphore::new(MAX_DOCS_IN_FLIGHT_SIZE as usize));
+    let (sender, mut receiver) = mpsc::channel(UPLOAD_CONCURRENCY);
+
+    let unpacking = tokio::task::spawn_blocking(move || {
+        check_archive(&archive)?;
+
+        unpack_archive(&archive, &in_flight, &sender)
+    });
+
+    let version = version.to_string();
+
+    let uploaded = futures_util::stream::poll_fn(|cx| receiver.poll_recv(cx))
+        .map(|file: DocsFile| {
+            let version = &version;
+
+            // the file's share of the in-flight limit is released once it has been stored
+            async move {
+                storage
+                    .put_docs_file(name, version, &file.path, &file.contents)
+                    .await
+            }
+        })
+        .buffer_unordered(UPLOAD_CONCURRENCY)
+        .try_collect::<()>()
+        .await;
+
+    // if storing a file failed, the receiver has been dropped, which stops the unpacking
+    let unpacked = unpacking
+        .await
+        .context("Docs unpacking task failed")
+        .map_err(StorageError::from)?;
+
+    uploaded?;
+
+    let count = unpacked.map_err(|error| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid docs archive: {error:#}"),
+        )
+    })?;
+
+    Ok(count)
+}
+
+/// A file read from a docs archive, holding its share of the in-flight limit.
+struct DocsFile {
+    path: String,
+    contents: Vec<u8>,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Check that every file in a gzipped docs tarball has a valid path, and that they are within
+/// the size limits, without keeping any of them in memory.
+fn check_archive(archive: &[u8]) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(archive));
+
+    let mut files = 0;
+    let mut total_size = 0;
+
+    for entry in archive.entries().context("Failed to read tarball")? {
+        let entry = entry.context("Failed to read tarball entry")?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry.path().context("Failed to read tarball entry path")?;
+
+        let Some(path) = normalize_path(&entry_path.to_string_lossy()) else {
+            bail!("{} is not a valid path", entry_path.display());
+        };
+
+        if entry.size() > MAX_DOCS_FILE_SIZE {
+            bail!("{path} is larger than the limit of {MAX_DOCS_FILE_SIZE} bytes");
+        }
+
+        total_size += entry.size();
+
+        if total_size > MAX_DOCS_UNPACKED_SIZE {
+            bail!("Files are larger than the limit of {MAX_DOCS_UNPACKED_SIZE} bytes");
+        }
+
+        files += 1;
+    }
+
+    if files == 0 {
+        bail!("Archive does not contain any files");
+    }
+
+    Ok(())
+}
+
+/// Read every file from a checked docs tarball and send it, along with its normalized path, to
+/// be stored.
+///
+/// Returns the number of files read.
+fn unpack_archive(
+    archive: &[u8],
+    in_flight: &Arc<Semaphore>,
+    sender: &mpsc::Sender<DocsFile>,
+) -> anyhow::Result<usize> {
+    let runtime = Handle::current();
+    let mut archive = tar::Archive::new(GzDecoder::new(archive));
+
+    let mut files = 0;
+
+    for entry in archive.entries().context("Failed to read tarball")? {
+        let mut entry = entry.context("Failed to read tarball entry")?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry.path().context("Failed to read tarball entry path")?;
+
+        let Some(path) = normalize_path(&entry_path.to_string_lossy()) else {
+            bail!("{} is not a valid path", entry_path.display());
+        };
+
+        // checked to be within MAX_DOCS_FILE_SIZE, which is below the in-flight limit
+        let size = entry.size() as u32;
+
+        let permit = runtime
+            .block_on(in_flight.clone().acquire_many_owned(size))
+            .context("In-flight limit was closed")?;
+
+        let mut contents = Vec::with_capacity(size as usize);
+
+        entry
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Failed to read {path} from tarball"))?;
+
+        let file = DocsFile {
+            path,
+            contents,
+            _permit: permit,
+        };
+
+        if sender.blocking_send(file).is_err() {
+            bail!("Stopped storing docs files");
+        }
+
+        files += 1;
+    }
+
+    Ok(files)
+}
+
+/// Normalize a relative path to use forward slashes without any `.` components.
+///
+/// Returns `None` for paths which are absolute, empty, or contain `..` components.
+fn normalize_path(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+
+    for component in std::path::Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(parts.join("/"))
+}
+
+async fn redirect_to_root(Path((_name, version)): Path<(String, String)>) -> Redirect {
+    // relative to the version, so that this works wherever the router is nested
+    Redirect::permanent(&format!("{version}/"))
+}
+
+async fn serve_root<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version)): Path<(String, String)>,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    S: StorageProvider,
+    A: AuthProvider + Sync,
+{
+    serve(&headers, &state, name, version, String::new()).await
+}
+
+async fn serve_file<I, S, A>(
+    headers: HeaderMap,
+    State(state): State<Arc<ServiceState<I, S, A>>>,
+    Path((name, version, path)): Path<(String, String, String)>,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    S: StorageProvider,
+    A: AuthProvider + Sync,
+{
+    let path = path.trim_start_matches('/').to_string();
+
+    serve(&headers, &state, name, version, path).await
+}
+
+async fn serve<I, S, A>(
+    headers: &HeaderMap,
+    state: &ServiceState<I, S, A>,
+    name: String,
+    version: String,
+    path: String,
+) -> axum::response::Result<Response>
+where
+    I: IndexProvider,
+    S: StorageProvider,
+    A: AuthProvider + Sync,
+{
+    let token = headers
+        .get(AUTHORIZATION)
+        .map(|x| x.to_str().or(Err(StatusCode::BAD_REQUEST)))
+        .transpose()?;
+
+    state.auth.auth_crate_download(token, &name).await?;
+
+    if version == "latest" {
+        let metadata = state.index.get_crate(&name).await?.crate_metadata;
+        let latest = metadata.max_stable_version.unwrap_or(metadata.max_version);
+
+        return Ok(Redirect::temporary(&format!("/docs/{name}/{latest}/{path}")).into_response());
+    }
+
+    let version: Version = version.parse().or(Err(StatusCode::NOT_FOUND))?;
+
+    // this also makes sure that the crate name can't be used to escape the crate's docs
+    state.index.confirm_existence(&name, &version).await?;
+
+    let file_path = if path.is_empty() || path.ends_with('/') {
+        format!("{path}index.html")
+    } else {
+        path.clone()
+    };
+
+    let file_path = normalize_path(&file_path).ok_or(StatusCode::NOT_FOUND)?;
+
+    let file = match state
+        .storage
+        .pull_docs_file(&name, &version.to_string(), &file_path)
+        .await
+    {
+        Ok(file) => file,
+        // rustdoc only writes an index page at the root if asked to, otherwise the docs of the
+        // crate itself are the place to start
+        Err(StorageError::NotFound) if path.is_empty() => {
+            let lib_name = name.replace('-', "_");
+
+            return Ok(Redirect::temporary(&format!("{lib_name}/index.html")).into_response());
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type(&file_path)),
+            (CONTENT_SECURITY_POLICY, DOCS_CONTENT_SECURITY_POLICY),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        file,
+    )
+        .into_response())
+}
+
+/// Guess the content type of a file of rustdoc output from its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+
+    match extension {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn handle_docs_fallback() -> StatusCode {
+    StatusCode::NOT_FOUND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// A gzipped tarball of files with the given paths, which are written as is rather than
+    /// validated by the tar builder.
+    fn archive(paths: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        for path in paths {
+            let mut header = tar::Header::new_gnu();
+
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder.append(&header, &b"docs"[..]).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize_path("foo/index.html").unwrap(), "foo/index.html");
+        assert_eq!(
+            normalize_path("./foo/./index.html").unwrap(),
+            "foo/index.html"
+        );
+        assert_eq!(normalize_path("foo//index.html").unwrap(), "foo/index.html");
+        assert_eq!(normalize_path("foo/").unwrap(), "foo");
+        assert_eq!(normalize_path("foo/bar/").unwrap(), "foo/bar");
+    }
+
+    #[test]
+    fn paths_escaping_the_docs_are_rejected() {
+        for path in [
+            "..",
+            "../index.html",
+            "foo/../../index.html",
+            "foo/..",
+            "/etc/passwd",
+            "/foo/index.html",
+            "",
+            ".",
+            "./",
+            "/",
+        ] {
+            assert_eq!(normalize_path(path), None, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn archives_with_escaping_paths_are_rejected() {
+        assert!(check_archive(&archive(&["foo/index.html", "./static/app.js"])).is_ok());
+
+        for path in ["../index.html", "foo/../../index.html", "/etc/passwd"] {
+            let error = check_archive(&archive(&["foo/index.html", path])).unwrap_err();
+
+            assert!(
+                error.to_string().contains("is not a valid path"),
+                "{path}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn archives_without_files_are_rejected() {
+        let error = check_archive(&archive(&[])).unwrap_err();
+
+        assert_eq!(error.to_string(), "Archive does not contain any files");
+    }
+}
@@ -1,15 +1,26 @@
+use crate::caching::apply_cache_control;
+use crate::upstream::Upstream;
 use crate::ServiceState;
 use axum::body::Bytes;
 use axum::extract::{Path, State};
 use axum::http::header::AUTHORIZATION;
 use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 use freighter_auth::AuthProvider;
-use freighter_index::IndexProvider;
-use freighter_storage::StorageProvider;
+use freighter_index::{DownloadCount, IndexError, IndexProvider};
+use freighter_storage::{StorageError, StorageProvider};
 use semver::Version;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// How often counted downloads are recorded in the index.
+const DOWNLOAD_RECORD_INTERVAL: Duration = Duration::from_secs(10);
 
 pub fn downloads_router<I, S, A>() -> Router<Arc<ServiceState<I, S, A>>>
 where
@@ -26,10 +37,10 @@ async fn serve_crate<I, S, A>(
     headers: HeaderMap,
     State(state): State<Arc<ServiceState<I, S, A>>>,
     Path((name, version)): Path<(String, Version)>,
-) -> axum::response::Result<Bytes>
+) -> axum::response::Result<Response>
 where
     I: IndexProvider,
-    S: StorageProvider,
+    S: StorageProvider + Sync,
     A: AuthProvider + Sync,
 {
     let token = headers
@@ -39,14 +50,187 @@ where
 
     state.auth.auth_crate_download(token, &name).await?;
 
-    let _is_yanked = state.index.confirm_existence(&name, &version).await?;
+    let crate_bytes = match state.index.confirm_existence(&name, &version).await {
+        Ok(_is_yanked) => {
+            let crate_bytes = state
+                .storage
+                .pull_crate(&name, &version.to_string())
+                .await?;
+
+            state.download_counter.increment(&name, &version);
+
+            crate_bytes
+        }
+        // crates which aren't hosted locally may be found upstream
+        Err(IndexError::NotFound) => match &state.upstream {
+            Some(upstream) => {
+                // other versions of the crate may exist locally, in which case it isn't proxied
+                match state.index.get_sparse_entry(&name).await {
+                    Err(IndexError::NotFound) => {}
+                    Ok(_) => return Err(IndexError::NotFound.into()),
+                    Err(error) => return Err(error.into()),
+                }
+
+                pull_upstream_crate(upstream, &state.storage, &name, &version).await?
+            }
+            None => return Err(IndexError::NotFound.into()),
+        },
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut response = crate_bytes.into_response();
+
+    apply_cache_control(
+        state.config.download_cache_control.as_deref(),
+        response.headers_mut(),
+    );
+
+    Ok(response)
+}
+
+/// Serve an upstream crate from storage if it has been downloaded before, or download and store it
+/// otherwise.
+async fn pull_upstream_crate<S>(
+    upstream: &Upstream,
+    storage: &S,
+    name: &str,
+    version: &Version,
+) -> axum::response::Result<Bytes>
+where
+    S: StorageProvider + Sync,
+{
+    match storage.pull_crate(name, &version.to_string()).await {
+        Ok(crate_bytes) => return Ok(crate_bytes),
+        Err(StorageError::NotFound) => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    let crate_bytes = upstream
+        .get_crate(storage, name, version)
+        .await
+        .map_err(IndexError::from)?
+        .ok_or(IndexError::NotFound)?;
+
+    // the crate can still be served even if it couldn't be stored, it'll just be downloaded again
+    if let Err(error) = storage
+        .put_crate(name, &version.to_string(), &crate_bytes)
+        .await
+    {
+        tracing::error!(?error, "Failed to store upstream crate");
+    }
+
+    Ok(crate_bytes)
+}
+
+/// Downloads of local crates which have yet to be recorded in the index.
+///
+/// Downloads are counted in memory and periodically recorded in bulk by a background task, so
+/// that serving crates doesn't wait on the index.
+/// Counts which haven't been recorded yet are lost if the server stops without running
+/// [`DownloadRecorder`] to completion.
+#[derive(Default)]
+pub struct DownloadCounter {
+    pending: Mutex<HashMap<(String, Version), u64>>,
+}
+
+impl DownloadCounter {
+    fn increment(&self, name: &str, version: &Version) {
+        *self
+            .pending
+            .lock()
+            .unwrap()
+            .entry((name.to_string(), version.clone()))
+            .or_default() += 1;
+    }
+
+    fn take(&self) -> Vec<DownloadCount> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+            .into_iter()
+            .map(|((crate_name, version), downloads)| DownloadCount {
+                crate_name,
+                version,
+                downloads,
+            })
+            .collect()
+    }
+
+    /// Put back counts which couldn't be recorded, to be retried later.
+    fn restore(&self, counts: Vec<DownloadCount>) {
+        let mut pending = self.pending.lock().unwrap();
+
+        for count in counts {
+            *pending
+                .entry((count.crate_name, count.version))
+                .or_default() += count.downloads;
+        }
+    }
+}
+
+/// Background task recording counted downloads in the index.
+///
+/// Returned along with the router by [`router`](crate::router), and must be run for downloads to
+/// be recorded.
+pub struct DownloadRecorder {
+    record: Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>,
+}
+
+impl DownloadRecorder {
+    pub(crate) fn new<I, S, A>(state: Arc<ServiceState<I, S, A>>) -> Self
+    where
+        I: IndexProvider + Send + Sync + 'static,
+        S: Send + Sync + 'static,
+        A: Send + Sync + 'static,
+    {
+        Self {
+            record: Box::new(move || {
+                let state = state.clone();
+
+                Box::pin(async move { record_download_counts(&state).await })
+            }),
+        }
+    }
+
+    /// Record counted downloads in the index every [`DOWNLOAD_RECORD_INTERVAL`] until `shutdown`
+    /// completes, and then once more.
+    ///
+    /// To avoid losing downloads, `shutdown` should complete only after the server has stopped
+    /// serving crates.
+    pub async fn run<F>(self, shutdown: F)
+    where
+        F: Future<Output = ()>,
+    {
+        let mut interval = tokio::time::interval(DOWNLOAD_RECORD_INTERVAL);
+
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut shutdown = pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (self.record)().await,
+                _ = &mut shutdown => break,
+            }
+        }
+
+        (self.record)().await;
+    }
+}
+
+async fn record_download_counts<I, S, A>(state: &ServiceState<I, S, A>)
+where
+    I: IndexProvider,
+{
+    let counts = state.download_counter.take();
+
+    if counts.is_empty() {
+        return;
+    }
 
-    let crate_bytes = state
-        .storage
-        .pull_crate(&name, &version.to_string())
-        .await?;
+    if let Err(error) = state.index.record_downloads(&counts).await {
+        tracing::error!(?error, "Failed to record download counts");
 
-    Ok(Bytes::from(crate_bytes))
+        state.download_counter.restore(counts);
+    }
 }
 
 async fn handle_downloads_fallback() -> StatusCode {
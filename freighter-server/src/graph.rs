@@ -0,0 +1,96 @@
+//! Resolution of the transitive dependency graph of a crate version from the index.
+//!
+//! Dependencies are resolved with [`freighter_index::resolve`], so optional dependencies are only
+//! followed when enabled, and dependencies from other registries are listed as unresolved.
+//! The licenses of every package in the graph are looked up together once it is resolved.
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use freighter_index::resolve::{all_features, Resolver};
+use freighter_index::{DependencyGraph, GraphPackage, IndexError, IndexProvider, PackageId};
+
+/// The features requested for the root of a graph.
+pub(crate) struct FeatureSelection {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub default_features: bool,
+}
+
+/// Resolve the dependency graph of a crate version with the selected features.
+pub(crate) async fn resolve_graph<I>(
+    index: &I,
+    root: &PackageId,
+    selection: &FeatureSelection,
+) -> axum::response::Result<DependencyGraph>
+where
+    I: IndexProvider,
+{
+    let root_version = index
+        .get_sparse_entry(&root.name)
+        .await?
+        .into_iter()
+        .find(|version| version.vers == root.version)
+        .ok_or(IndexError::NotFound)?;
+
+    let features = all_features(&root_version);
+
+    let mut requested = selection.features.clone();
+
+    if selection.all_features {
+        requested.extend(features.iter().cloned());
+    }
+
+    if selection.default_features && features.iter().any(|feature| feature == "default") {
+        requested.push("default".to_string());
+    }
+
+    for feature in &requested {
+        let known = match feature.split_once('/') {
+            Some((dependency, _)) => root_version
+                .deps
+                .iter()
+                .any(|dep| dep.name == dependency.trim_end_matches('?')),
+            None => features.contains(feature),
+        };
+
+        if !known {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("{} {} has no feature {feature}", root.name, root.version),
+            )
+                .into());
+        }
+    }
+
+    let mut resolver = Resolver::new(|crate_name: String| async move {
+        index
+            .get_sparse_entry(&crate_name)
+            .await
+            .with_context(|| format!("Failed to fetch index entry for {crate_name}"))
+    });
+
+    resolver.add_root(root_version, requested);
+
+    let resolved = resolver.resolve().await.map_err(IndexError::from)?;
+
+    let ids: Vec<PackageId> = resolved.keys().cloned().collect();
+    let mut licenses = index.get_licenses(&ids).await?;
+
+    let packages = resolved
+        .into_iter()
+        .map(|(id, package)| GraphPackage {
+            license: licenses.remove(&id).flatten(),
+            name: id.name,
+            version: id.version,
+            checksum: package.version.cksum,
+            features: package.features.into_iter().collect(),
+            dependencies: package.dependencies,
+            unresolved: package.unresolved,
+        })
+        .collect();
+
+    Ok(DependencyGraph {
+        root: root.clone(),
+        packages,
+    })
+}
@@ -1,22 +1,27 @@
-use crate::ServiceState;
+use crate::caching::{apply_cache_control, Validators};
+use crate::{ServiceConfig, ServiceState};
 use axum::extract::{Path, State};
-use axum::http::header::AUTHORIZATION;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
 use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::{Json, Router};
-use axum_extra::extract::JsonLines;
-use axum_extra::json_lines::AsResponse;
 use freighter_auth::AuthProvider;
-use freighter_index::{CrateVersion, IndexProvider};
-use serde::{Deserialize, Serialize};
-use std::convert::Infallible;
+use freighter_index::{
+    IndexError, IndexProvider, RegistryConfig, RenderedSparseEntry, SparseEntryRevision,
+};
+use freighter_storage::StorageProvider;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tokio_stream::{Stream, StreamExt};
+use std::time::UNIX_EPOCH;
+
+/// Content type of sparse index entries, which are newline-delimited JSON.
+const SPARSE_ENTRY_CONTENT_TYPE: &str = "application/x-ndjson";
 
 pub fn index_router<I, S, A>() -> Router<Arc<ServiceState<I, S, A>>>
 where
     I: IndexProvider + Send + Sync + 'static,
-    S: Send + Sync + 'static,
+    S: StorageProvider + Send + Sync + 'static,
     A: AuthProvider + Send + Sync + 'static,
 {
     Router::new()
@@ -25,12 +30,6 @@ where
         .fallback(handle_index_fallback)
 }
 
-#[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
-struct RegistryConfig {
-    dl: String,
-    api: String,
-}
-
 async fn config<I, S, A>(State(state): State<Arc<ServiceState<I, S, A>>>) -> Json<RegistryConfig> {
     RegistryConfig {
         dl: state.config.download_endpoint.clone(),
@@ -43,11 +42,10 @@ async fn get_sparse_meta<I, S, A>(
     headers: HeaderMap,
     State(state): State<Arc<ServiceState<I, S, A>>>,
     Path((_, _, crate_name)): Path<(String, String, String)>,
-) -> axum::response::Result<
-    JsonLines<impl Stream<Item = Result<CrateVersion, Infallible>>, AsResponse>,
->
+) -> axum::response::Result<Response>
 where
     I: IndexProvider,
+    S: StorageProvider + Sync,
     A: AuthProvider + Sync,
 {
     let token = headers
@@ -57,11 +55,72 @@ where
 
     state.auth.auth_index_fetch(token, &crate_name).await?;
 
-    let crate_versions = state.index.get_sparse_entry(&crate_name).await?;
+    // entries come rendered, and with a caching index provider usually straight from memory
+    let entry = match state.index.get_rendered_sparse_entry(&crate_name).await {
+        Ok(entry) => entry,
+        // crates which aren't hosted locally may be found upstream
+        Err(IndexError::NotFound) => match &state.upstream {
+            Some(upstream) => RenderedSparseEntry {
+                contents: upstream
+                    .get_entry(&state.storage, &crate_name)
+                    .await
+                    .map_err(IndexError::from)?
+                    .ok_or(IndexError::NotFound)?,
+                revision: None,
+            },
+            None => return Err(IndexError::NotFound.into()),
+        },
+        Err(error) => return Err(error.into()),
+    };
+
+    // if the index doesn't track revisions, fall back to hashing the entry
+    let validators = match &entry.revision {
+        Some(revision) => revision_validators(revision),
+        None => Validators {
+            etag: format!("\"{:x}\"", Sha256::digest(&entry.contents)),
+            last_modified: None,
+        },
+    };
+
+    if validators.is_fresh(&headers) {
+        return Ok(not_modified(&state.config, &validators));
+    }
+
+    let mut response =
+        ([(CONTENT_TYPE, SPARSE_ENTRY_CONTENT_TYPE)], entry.contents).into_response();
+
+    validators.apply(response.headers_mut());
+    apply_cache_control(
+        state.config.index_cache_control.as_deref(),
+        response.headers_mut(),
+    );
+
+    Ok(response)
+}
+
+fn revision_validators(revision: &SparseEntryRevision) -> Validators {
+    let timestamp = revision
+        .last_modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Validators {
+        etag: format!("\"{}-{timestamp}\"", revision.revision),
+        last_modified: Some(revision.last_modified),
+    }
+}
+
+fn not_modified(config: &ServiceConfig, validators: &Validators) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
 
-    let resp = JsonLines::new(tokio_stream::iter(crate_versions).map(|c| Ok(c)));
+    validators.apply(response.headers_mut());
+    apply_cache_control(
+        config.index_cache_control.as_deref(),
+        response.headers_mut(),
+    );
 
-    Ok(resp)
+    response
 }
 
 async fn handle_index_fallback() -> StatusCode {
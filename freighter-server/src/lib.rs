@@ -5,30 +5,78 @@ use axum::middleware::{from_fn, Next};
 use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
+use downloads::{DownloadCounter, DownloadRecorder};
 use freighter_auth::AuthProvider;
 use freighter_index::IndexProvider;
 use freighter_storage::StorageProvider;
 use metrics::{histogram, increment_counter};
 use serde::Deserialize;
+use source::FileListingCache;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Instant;
 use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::classify::StatusInRangeAsFailures;
 use tower_http::trace::{DefaultOnFailure, TraceLayer};
+use upstream::{Upstream, UpstreamConfig};
 
 pub mod index;
 
 pub mod api;
 
+pub mod docs;
+
 pub mod downloads;
 
+pub mod source;
+
+pub mod upstream;
+
+mod caching;
+
+mod diff;
+
+mod graph;
+
+mod readme;
+
+mod sbom;
+
+mod tarball;
+
 #[derive(Clone, Deserialize)]
 pub struct ServiceConfig {
     pub address: SocketAddr,
     pub download_endpoint: String,
     pub api_endpoint: String,
+    /// URL of the sparse index, as cargo is configured with it, such as
+    /// `https://example.com/index/`.
+    ///
+    /// If not set, the index is assumed to be served under `index/` of the `api_endpoint`.
+    pub index_endpoint: Option<String>,
     pub metrics_address: SocketAddr,
+    /// Value of the `Cache-Control` header sent with sparse index entries.
+    ///
+    /// If not set, no `Cache-Control` header is sent.
+    pub index_cache_control: Option<String>,
+    /// Value of the `Cache-Control` header sent with crate downloads.
+    ///
+    /// If not set, no `Cache-Control` header is sent.
+    pub download_cache_control: Option<String>,
+    /// Upstream registry to proxy crates which aren't hosted locally from.
+    ///
+    /// If not set, only local crates are served.
+    pub upstream: Option<UpstreamConfig>,
+}
+
+impl ServiceConfig {
+    /// The URL of the sparse index, ending with a `/`.
+    pub fn index_url(&self) -> String {
+        match &self.index_endpoint {
+            Some(index_endpoint) => format!("{}/", index_endpoint.trim_end_matches('/')),
+            None => format!("{}/index/", self.api_endpoint.trim_end_matches('/')),
+        }
+    }
 }
 
 pub struct ServiceState<I, S, A> {
@@ -36,25 +84,36 @@ pub struct ServiceState<I, S, A> {
     pub index: I,
     pub storage: S,
     pub auth: A,
+    pub upstream: Option<Upstream>,
+    pub download_counter: DownloadCounter,
+    pub file_listings: FileListingCache,
 }
 
 impl<I, S, A> ServiceState<I, S, A> {
     pub fn new(config: ServiceConfig, index: I, storage: S, auth: A) -> Self {
+        let upstream = config.upstream.as_ref().map(Upstream::new);
+
         Self {
             config,
             index,
             storage,
             auth,
+            upstream,
+            download_counter: DownloadCounter::default(),
+            file_listings: FileListingCache::default(),
         }
     }
 }
 
+/// Build the router of the service, along with the task recording downloads in the index.
+///
+/// The [`DownloadRecorder`] must be run for downloads to be recorded.
 pub fn router<I, S, A>(
     config: ServiceConfig,
     index_client: I,
     storage_client: S,
     auth_client: A,
-) -> Router
+) -> (Router, DownloadRecorder)
 where
     I: IndexProvider + Send + Sync + 'static,
     S: StorageProvider + Clone + Send + Sync + 'static,
@@ -67,9 +126,12 @@ where
         auth_client,
     ));
 
-    Router::new()
+    let download_recorder = DownloadRecorder::new(state.clone());
+
+    let router = Router::new()
         .nest("/downloads", downloads::downloads_router())
         .nest("/index", index::index_router())
+        .nest("/docs", docs::docs_router())
         .nest("/api/v1/crates", api::api_router())
         .route("/me", get(login))
         .with_state(state)
@@ -89,7 +151,9 @@ where
                 })
                 .on_failure(DefaultOnFailure::new()),
         )
-        .layer(from_fn(metrics_layer))
+        .layer(from_fn(metrics_layer));
+
+    (router, download_recorder)
 }
 
 async fn metrics_layer<B>(request: Request<B>, next: Next<B>) -> Response {
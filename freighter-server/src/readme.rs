@@ -0,0 +1,84 @@
+//! Extraction and rendering of crate READMEs.
+//!
+//! READMEs are read out of the `.crate` file when a version is published and stored in the index.
+//! Markdown READMEs are rendered to HTML which is then sanitized, so that it is safe to embed in
+//! other pages, while anything else is shown as preformatted text.
+
+use crate::tarball;
+use freighter_index::VersionReadme;
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{html, Options, Parser};
+use std::path::{Component, Path};
+
+/// Largest README which will be extracted from a crate.
+const MAX_README_SIZE: u64 = 1024 * 1024;
+
+/// Extract the README of a version from its `.crate` file, given its `readme_file`.
+///
+/// This reads through the crate file, so it should be run on a blocking thread.
+pub(crate) fn extract_readme(
+    readme_file: &str,
+    crate_bytes: &[u8],
+) -> anyhow::Result<Option<String>> {
+    let mut path = Path::new(readme_file);
+
+    // cargo places READMEs from outside of the package at its root
+    if path.components().any(|c| c == Component::ParentDir) {
+        match path.file_name() {
+            Some(file_name) => path = Path::new(file_name),
+            None => return Ok(None),
+        }
+    }
+
+    let readme = tarball::read_file(crate_bytes, path, MAX_README_SIZE)?;
+
+    Ok(readme.map(|readme| String::from_utf8_lossy(&readme).into_owned()))
+}
+
+/// Render a README to sanitized HTML.
+///
+/// READMEs can be large, so this should be run on a blocking thread.
+pub(crate) fn render_readme(readme: &VersionReadme) -> String {
+    if !is_markdown(readme) {
+        let mut rendered = String::from("<pre>");
+
+        // writing to a string can't fail
+        escape_html(&mut rendered, &readme.contents).unwrap();
+        rendered.push_str("</pre>");
+
+        return rendered;
+    }
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+
+    let mut rendered = String::new();
+
+    html::push_html(&mut rendered, Parser::new_ext(&readme.contents, options));
+
+    // task list items are rendered as checkboxes
+    ammonia::Builder::default()
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["checked", "disabled", "type"])
+        .link_rel(Some("nofollow noopener noreferrer"))
+        .clean(&rendered)
+        .to_string()
+}
+
+/// Whether a README is markdown, going by its file extension.
+///
+/// READMEs without a known file name are assumed to be markdown, like on crates.io.
+pub(crate) fn is_markdown(readme: &VersionReadme) -> bool {
+    let Some(file) = &readme.file else {
+        return true;
+    };
+
+    match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ["md", "markdown", "mdown", "mkdn", "mkd"]
+            .iter()
+            .any(|md| ext.eq_ignore_ascii_case(md)),
+        None => false,
+    }
+}
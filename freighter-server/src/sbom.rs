@@ -0,0 +1,541 @@
+//! Rendering of dependency graphs as software bills of materials.
+//!
+//! Graphs can be rendered as [CycloneDX](https://cyclonedx.org/) 1.5 and
+//! [SPDX](https://spdx.dev/) 2.3 documents, in their JSON forms.
+//! Packages are identified by their [package URL](https://github.com/package-url/purl-spec),
+//! qualified with the URL of this registry's index so they aren't mistaken for crates.io crates,
+//! and carry the SHA-256 checksum of their `.crate` file and their declared license.
+//! Dependencies on crates from other registries are included as components without a version,
+//! noting the requirement and registry they come from.
+
+use crate::upstream::render_download_url;
+use crate::ServiceConfig;
+use freighter_index::{DependencyGraph, DependencyKind, UnresolvedDependency};
+use semver::Version;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Render a dependency graph as a CycloneDX document.
+pub(crate) fn cyclonedx(graph: &DependencyGraph, config: &ServiceConfig) -> Value {
+    let index_url = config.index_url();
+    let mut root = None;
+    let mut components = Vec::new();
+
+    for package in &graph.packages {
+        let purl = purl(&index_url, &package.name, &package.version);
+
+        let mut component = json!({
+            "type": "library",
+            "bom-ref": purl,
+            "name": package.name,
+            "version": package.version.to_string(),
+            "purl": purl,
+            "hashes": [{ "alg": "SHA-256", "content": package.checksum }],
+        });
+
+        if let Some(license) = &package.license {
+            component["licenses"] = json!([{ "expression": license_expression(license) }]);
+        }
+
+        if package.name == graph.root.name && package.version == graph.root.version {
+            root = Some(component);
+        } else {
+            components.push(component);
+        }
+    }
+
+    let unresolved: BTreeMap<String, &UnresolvedDependency> = graph
+        .packages
+        .iter()
+        .flat_map(|package| &package.unresolved)
+        .map(|dependency| (unresolved_ref(dependency), dependency))
+        .collect();
+
+    for (bom_ref, dependency) in unresolved {
+        components.push(json!({
+            "type": "library",
+            "bom-ref": bom_ref,
+            "name": dependency.name,
+            "properties": [
+                { "name": "freighter:registry", "value": dependency.registry },
+                { "name": "freighter:requirement", "value": dependency.req.to_string() },
+            ],
+        }));
+    }
+
+    let dependencies: Vec<Value> = graph
+        .packages
+        .iter()
+        .map(|package| {
+            let mut depends_on: Vec<String> = package
+                .dependencies
+                .iter()
+                .map(|dependency| purl(&index_url, &dependency.name, &dependency.version))
+                .chain(package.unresolved.iter().map(unresolved_ref))
+                .collect();
+
+            depends_on.sort();
+            depends_on.dedup();
+
+            json!({
+                "ref": purl(&index_url, &package.name, &package.version),
+                "dependsOn": depends_on,
+            })
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": [{ "name": "freighter", "version": env!("CARGO_PKG_VERSION") }],
+            "component": root,
+        },
+        "components": components,
+        "dependencies": dependencies,
+    })
+}
+
+/// Render a dependency graph as an SPDX document.
+///
+/// The document namespace is made unique to the graph by a hash of its contents.
+pub(crate) fn spdx(graph: &DependencyGraph, config: &ServiceConfig) -> Value {
+    let index_url = config.index_url();
+
+    let mut packages: Vec<Value> = graph
+        .packages
+        .iter()
+        .map(|package| {
+            json!({
+                "name": package.name,
+                "SPDXID": package_spdx_id(&package.name, &package.version.to_string()),
+                "versionInfo": package.version.to_string(),
+                "downloadLocation": render_download_url(
+                    &config.download_endpoint,
+                    &package.name,
+                    &package.version,
+                    &package.checksum,
+                ),
+                "filesAnalyzed": false,
+                "checksums": [{ "algorithm": "SHA256", "checksumValue": package.checksum }],
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": package
+                    .license
+                    .as_deref()
+                    .map_or("NOASSERTION".to_string(), license_expression),
+                "copyrightText": "NOASSERTION",
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl(&index_url, &package.name, &package.version),
+                }],
+            })
+        })
+        .collect();
+
+    let unresolved: BTreeMap<String, &UnresolvedDependency> = graph
+        .packages
+        .iter()
+        .flat_map(|package| &package.unresolved)
+        .map(|dependency| (unresolved_spdx_id(dependency), dependency))
+        .collect();
+
+    for (id, dependency) in unresolved {
+        packages.push(json!({
+            "name": dependency.name,
+            "SPDXID": id,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": "NOASSERTION",
+            "copyrightText": "NOASSERTION",
+            "comment": format!(
+                "Unresolved dependency on {} from the registry at {}",
+                dependency.req, dependency.registry
+            ),
+        }));
+    }
+
+    let root_id = package_spdx_id(&graph.root.name, &graph.root.version.to_string());
+
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": root_id,
+    })];
+
+    for package in &graph.packages {
+        let package_id = package_spdx_id(&package.name, &package.version.to_string());
+
+        let dependencies = package
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                let id = package_spdx_id(&dependency.name, &dependency.version.to_string());
+
+                (id, &dependency.kind)
+            })
+            .chain(
+                package
+                    .unresolved
+                    .iter()
+                    .map(|dependency| (unresolved_spdx_id(dependency), &dependency.kind)),
+            );
+
+        for (dependency_id, kind) in dependencies {
+            let relationship = match kind {
+                DependencyKind::Build => json!({
+                    "spdxElementId": dependency_id,
+                    "relationshipType": "BUILD_DEPENDENCY_OF",
+                    "relatedSpdxElement": package_id,
+                }),
+                _ => json!({
+                    "spdxElementId": package_id,
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": dependency_id,
+                }),
+            };
+
+            if !relationships.contains(&relationship) {
+                relationships.push(relationship);
+            }
+        }
+    }
+
+    // serializing the graph can't fail
+    let graph_hash = Sha256::digest(serde_json::to_vec(graph).unwrap());
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("{}-{}", graph.root.name, graph.root.version),
+        "documentNamespace": format!(
+            "{}/spdxdocs/{}-{}-{graph_hash:x}",
+            config.api_endpoint.trim_end_matches('/'),
+            graph.root.name,
+            graph.root.version
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [format!("Tool: freighter-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// Build the package URL of a crate served from the index at `index_url`.
+///
+/// Without a `repository_url` qualifier, a cargo package URL refers to a crate on crates.io.
+fn purl(index_url: &str, name: &str, version: &Version) -> String {
+    let repository_url: String = index_url
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~:".contains(&b) {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect();
+
+    format!("pkg:cargo/{name}@{version}?repository_url={repository_url}")
+}
+
+/// The CycloneDX reference of a dependency from another registry.
+fn unresolved_ref(dependency: &UnresolvedDependency) -> String {
+    format!(
+        "unresolved:{}#{}@{}",
+        dependency.registry, dependency.name, dependency.req
+    )
+}
+
+fn package_spdx_id(name: &str, version: &str) -> String {
+    spdx_id("Package", &[name, version])
+}
+
+fn unresolved_spdx_id(dependency: &UnresolvedDependency) -> String {
+    spdx_id(
+        "Unresolved",
+        &[
+            &dependency.registry,
+            &dependency.name,
+            &dependency.req.to_string(),
+        ],
+    )
+}
+
+/// Build an SPDX identifier, which may only contain letters, digits, `.` and `-`.
+///
+/// Any other byte of the parts, including `-`, is escaped as `-` followed by two hex digits, and
+/// the parts are separated by `--`, so that distinct parts never share an identifier.
+fn spdx_id(kind: &str, parts: &[&str]) -> String {
+    let escaped: Vec<String> = parts
+        .iter()
+        .map(|part| {
+            part.bytes()
+                .map(|b| {
+                    if b.is_ascii_alphanumeric() || b == b'.' {
+                        char::from(b).to_string()
+                    } else {
+                        format!("-{b:02X}")
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    format!("SPDXRef-{kind}-{}", escaped.join("--"))
+}
+
+/// Convert a license from a manifest into an SPDX license expression.
+///
+/// Older crates separate alternative licenses with `/` rather than `OR`.
+fn license_expression(license: &str) -> String {
+    license
+        .split('/')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+fn timestamp() -> String {
+    // formatting the current time as rfc 3339 can't fail
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use freighter_index::{GraphDependency, GraphPackage, PackageId};
+    use std::collections::HashSet;
+
+    fn config() -> ServiceConfig {
+        ServiceConfig {
+            address: "127.0.0.1:3000".parse().unwrap(),
+            download_endpoint: "https://registry.example.com/downloads".to_string(),
+            api_endpoint: "https://registry.example.com".to_string(),
+            index_endpoint: None,
+            metrics_address: "127.0.0.1:3001".parse().unwrap(),
+            index_cache_control: None,
+            download_cache_control: None,
+            upstream: None,
+        }
+    }
+
+    fn package(
+        name: &str,
+        version: &str,
+        license: Option<&str>,
+        dependencies: &[(&str, &str, DependencyKind)],
+    ) -> GraphPackage {
+        GraphPackage {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            checksum: format!("{name}-{version}-checksum"),
+            license: license.map(str::to_string),
+            features: Vec::new(),
+            dependencies: dependencies
+                .iter()
+                .map(|(name, version, kind)| GraphDependency {
+                    name: name.to_string(),
+                    version: version.parse().unwrap(),
+                    req: "*".parse().unwrap(),
+                    kind: kind.clone(),
+                    target: None,
+                })
+                .collect(),
+            unresolved: Vec::new(),
+        }
+    }
+
+    /// A graph with packages whose names and versions differ only in characters which aren't
+    /// allowed in SPDX identifiers.
+    fn graph() -> DependencyGraph {
+        let mut app = package(
+            "app",
+            "1.0.0",
+            Some("MIT/Apache-2.0"),
+            &[
+                ("foo_bar", "1.0.0", DependencyKind::Normal),
+                ("foo-bar", "1.0.0", DependencyKind::Normal),
+                ("lib", "1.0.0-a", DependencyKind::Build),
+            ],
+        );
+
+        app.unresolved.push(UnresolvedDependency {
+            name: "elsewhere".to_string(),
+            req: "^2".parse().unwrap(),
+            registry: "https://other.example.com/index".to_string(),
+            kind: DependencyKind::Normal,
+            target: None,
+        });
+
+        DependencyGraph {
+            root: PackageId {
+                name: "app".to_string(),
+                version: "1.0.0".parse().unwrap(),
+            },
+            packages: vec![
+                app,
+                package(
+                    "foo-bar",
+                    "1.0.0",
+                    None,
+                    &[("lib", "1.0.0+a", DependencyKind::Normal)],
+                ),
+                package("foo_bar", "1.0.0", Some("MIT"), &[]),
+                package("lib", "1.0.0+a", None, &[]),
+                package("lib", "1.0.0-a", None, &[]),
+            ],
+        }
+    }
+
+    const APP_PURL: &str =
+        "pkg:cargo/app@1.0.0?repository_url=https:%2F%2Fregistry.example.com%2Findex%2F";
+
+    #[test]
+    fn cyclonedx_documents_reference_local_packages() {
+        let document = cyclonedx(&graph(), &config());
+
+        let root = &document["metadata"]["component"];
+
+        assert_eq!(root["bom-ref"], APP_PURL);
+        assert_eq!(root["purl"], APP_PURL);
+        assert_eq!(root["licenses"][0]["expression"], "MIT OR Apache-2.0");
+
+        let components = document["components"].as_array().unwrap();
+
+        // every package but the root, along with the unresolved dependency
+        assert_eq!(components.len(), 5);
+
+        let refs: HashSet<&str> = components
+            .iter()
+            .chain([root])
+            .map(|component| component["bom-ref"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(refs.len(), 6);
+
+        for component in components.iter().filter(|c| c["purl"].is_string()) {
+            let purl = component["purl"].as_str().unwrap();
+
+            assert!(purl.starts_with("pkg:cargo/"), "{purl}");
+            assert!(
+                purl.ends_with("?repository_url=https:%2F%2Fregistry.example.com%2Findex%2F"),
+                "{purl}"
+            );
+        }
+
+        let unresolved = components
+            .iter()
+            .find(|component| component["name"] == "elsewhere")
+            .unwrap();
+
+        assert!(unresolved.get("purl").is_none());
+        assert_eq!(
+            unresolved["properties"][0]["value"],
+            "https://other.example.com/index"
+        );
+
+        let dependencies = document["dependencies"].as_array().unwrap();
+
+        assert_eq!(dependencies.len(), 5);
+
+        for dependency in dependencies {
+            assert!(refs.contains(dependency["ref"].as_str().unwrap()));
+
+            for depends_on in dependency["dependsOn"].as_array().unwrap() {
+                assert!(refs.contains(depends_on.as_str().unwrap()), "{depends_on}");
+            }
+        }
+
+        let app = dependencies
+            .iter()
+            .find(|dependency| dependency["ref"] == APP_PURL)
+            .unwrap();
+
+        assert_eq!(app["dependsOn"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn spdx_documents_have_distinct_identifiers() {
+        let document = spdx(&graph(), &config());
+
+        let packages = document["packages"].as_array().unwrap();
+
+        assert_eq!(packages.len(), 6);
+
+        let ids: HashSet<&str> = packages
+            .iter()
+            .map(|package| package["SPDXID"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(ids.len(), 6);
+
+        for id in &ids {
+            assert!(
+                id.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'),
+                "{id}"
+            );
+        }
+
+        let app = packages
+            .iter()
+            .find(|package| package["name"] == "app")
+            .unwrap();
+
+        assert_eq!(app["licenseDeclared"], "MIT OR Apache-2.0");
+        assert_eq!(app["externalRefs"][0]["referenceLocator"], APP_PURL);
+        assert_eq!(
+            app["downloadLocation"],
+            "https://registry.example.com/downloads/app/1.0.0/download"
+        );
+
+        let app_id = app["SPDXID"].as_str().unwrap();
+        let relationships = document["relationships"].as_array().unwrap();
+
+        assert_eq!(relationships[0]["spdxElementId"], "SPDXRef-DOCUMENT");
+        assert_eq!(relationships[0]["relationshipType"], "DESCRIBES");
+        assert_eq!(relationships[0]["relatedSpdxElement"], app_id);
+
+        for relationship in &relationships[1..] {
+            assert!(ids.contains(relationship["spdxElementId"].as_str().unwrap()));
+            assert!(ids.contains(relationship["relatedSpdxElement"].as_str().unwrap()));
+        }
+
+        // app depends on both foo crates and the unresolved crate, lib is a build dependency
+        let depends_on = relationships
+            .iter()
+            .filter(|r| r["relationshipType"] == "DEPENDS_ON" && r["spdxElementId"] == app_id)
+            .count();
+        let build_dependencies = relationships
+            .iter()
+            .filter(|r| {
+                r["relationshipType"] == "BUILD_DEPENDENCY_OF" && r["relatedSpdxElement"] == app_id
+            })
+            .count();
+
+        assert_eq!((depends_on, build_dependencies), (3, 1));
+    }
+
+    #[test]
+    fn configured_index_endpoint_is_used_in_package_urls() {
+        let mut config = config();
+        config.index_endpoint = Some("https://index.example.com".to_string());
+
+        let document = cyclonedx(&graph(), &config);
+
+        assert_eq!(
+            document["metadata"]["component"]["purl"],
+            "pkg:cargo/app@1.0.0?repository_url=https:%2F%2Findex.example.com%2F"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Browsing of the sources of published crates.
+//!
+//! The files of a version are listed by reading its `.crate` file from the storage provider.
+//! Since published crates never change, parsed listings are kept in memory, so that browsing
+//! through a crate doesn't require its tarball to be listed again for every file.
+//! Files larger than [`MAX_SOURCE_FILE_SIZE`] are listed, but their contents aren't served.
+//! Tarballs are decompressed on blocking threads, so that large crates don't stall the executor.
+
+use crate::tarball;
+use anyhow::Context;
+use axum::body::Bytes;
+use freighter_index::CrateFile;
+use freighter_storage::{StorageError, StorageProvider, StorageResult};
+use lru::LruCache;
+use semver::Version;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Largest file whose contents will be served.
+pub const MAX_SOURCE_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Maximum number of file listings to keep in memory.
+const LISTING_CACHE_CAPACITY: usize = 1_000;
+
+/// Name and version of a crate.
+type ListingKey = (String, Version);
+
+/// In-memory cache of the file listings of crate versions.
+pub struct FileListingCache {
+    listings: Mutex<LruCache<ListingKey, Arc<Vec<CrateFile>>>>,
+}
+
+impl Default for FileListingCache {
+    fn default() -> Self {
+        Self {
+            listings: Mutex::new(LruCache::new(
+                NonZeroUsize::new(LISTING_CACHE_CAPACITY).unwrap(),
+            )),
+        }
+    }
+}
+
+impl FileListingCache {
+    /// Get the files of a crate version, listing its `.crate` file if it isn't cached.
+    pub async fn get<S>(
+        &self,
+        storage: &S,
+        crate_name: &str,
+        version: &Version,
+    ) -> StorageResult<Arc<Vec<CrateFile>>>
+    where
+        S: StorageProvider,
+    {
+        if let Some(files) = self.get_cached(crate_name, version) {
+            return Ok(files);
+        }
+
+        let crate_bytes = storage.pull_crate(crate_name, &version.to_string()).await?;
+
+        self.list(crate_name, version, crate_bytes).await
+    }
+
+    /// Read a file from a crate version.
+    ///
+    /// Returns [`SourceFile::TooLarge`] instead of the contents of files larger than
+    /// [`MAX_SOURCE_FILE_SIZE`].
+    pub async fn read<S>(
+        &self,
+        storage: &S,
+        crate_name: &str,
+        version: &Version,
+        path: &str,
+    ) -> StorageResult<SourceFile>
+    where
+        S: StorageProvider,
+    {
+        let crate_bytes = storage.pull_crate(crate_name, &version.to_string()).await?;
+
+        let files = match self.get_cached(crate_name, version) {
+            Some(files) => files,
+            None => self.list(crate_name, version, crate_bytes.clone()).await?,
+        };
+
+        let Some(file) = files.iter().find(|file| file.path == path) else {
+            return Err(StorageError::NotFound);
+        };
+
+        if file.size > MAX_SOURCE_FILE_SIZE {
+            return Ok(SourceFile::TooLarge(file.size));
+        }
+
+        let path = path.to_string();
+
+        let contents = tokio::task::spawn_blocking(move || {
+            tarball::read_file(&crate_bytes, Path::new(&path), MAX_SOURCE_FILE_SIZE)
+        })
+        .await
+        .context("File reading task failed")?
+        .context("Failed to read file from crate")?
+        .ok_or(StorageError::NotFound)?;
+
+        Ok(SourceFile::Contents(contents.into()))
+    }
+
+    fn get_cached(&self, crate_name: &str, version: &Version) -> Option<Arc<Vec<CrateFile>>> {
+        self.listings
+            .lock()
+            .unwrap()
+            .get(&(crate_name.to_string(), version.clone()))
+            .cloned()
+    }
+
+    async fn list(
+        &self,
+        crate_name: &str,
+        version: &Version,
+        crate_bytes: Bytes,
+    ) -> StorageResult<Arc<Vec<CrateFile>>> {
+        let files = tokio::task::spawn_blocking(move || tarball::list_files(&crate_bytes))
+            .await
+            .context("File listing task failed")?
+            .context("Failed to list crate files")?;
+
+        let files = Arc::new(files);
+
+        self.listings
+            .lock()
+            .unwrap()
+            .put((crate_name.to_string(), version.clone()), files.clone());
+
+        Ok(files)
+    }
+}
+
+/// The result of reading a file from a crate.
+pub enum SourceFile {
+    Contents(Bytes),
+    /// The file is too large to be served, with its size in bytes.
+    TooLarge(u64),
+}
@@ -0,0 +1,167 @@
+//! Reading the contents of `.crate` files.
+//!
+//! A `.crate` file is a gzipped tarball, in which every file of the package is placed under a
+//! `{name}-{version}` directory.
+
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use freighter_index::CrateFile;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Read a file from a `.crate`, given its path relative to the root of the package.
+///
+/// Returns `None` if there is no such file, and an error if it is larger than `max_size` bytes.
+pub(crate) fn read_file(
+    crate_bytes: &[u8],
+    path: &Path,
+    max_size: u64,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(crate_bytes));
+
+    for entry in archive.entries().context("Failed to read crate tarball")? {
+        let mut entry = entry.context("Failed to read crate tarball entry")?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        if package_path(
+            &entry
+                .path()
+                .context("Failed to read crate tarball entry path")?,
+        ) != path
+        {
+            continue;
+        }
+
+        let size = entry.size();
+
+        if size > max_size {
+            bail!(
+                "{} is {size} bytes, which is more than the limit of {max_size}",
+                path.display()
+            );
+        }
+
+        let mut contents = Vec::with_capacity(size as usize);
+
+        entry
+            .read_to_end(&mut contents)
+            .context("Failed to read file from crate tarball")?;
+
+        return Ok(Some(contents));
+    }
+
+    Ok(None)
+}
+
+/// List the files in a `.crate`, sorted by path.
+pub(crate) fn list_files(crate_bytes: &[u8]) -> anyhow::Result<Vec<CrateFile>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(crate_bytes));
+
+    let mut files = Vec::new();
+
+    for entry in archive.entries().context("Failed to read crate tarball")? {
+        let entry = entry.context("Failed to read crate tarball entry")?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = package_path(
+            &entry
+                .path()
+                .context("Failed to read crate tarball entry path")?,
+        );
+
+        files.push(CrateFile {
+            path: path.to_string_lossy().into_owned(),
+            size: entry.size(),
+            mode: entry
+                .header()
+                .mode()
+                .context("Failed to read crate tarball entry mode")?,
+        });
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(files)
+}
+
+/// A file read from a `.crate` by [`read_files`].
+pub(crate) struct FileContents {
+    /// SHA-256 hash of the file.
+    pub sha256: Vec<u8>,
+    /// The contents of the file, unless it was too large to be read.
+    pub contents: Option<Vec<u8>>,
+}
+
+/// Read every file in a `.crate`, keyed by path.
+///
+/// Files larger than `max_file_size` bytes, or which would bring the total size read above
+/// `max_total_size` bytes, are only hashed, without keeping their contents.
+pub(crate) fn read_files(
+    crate_bytes: &[u8],
+    max_file_size: u64,
+    max_total_size: u64,
+) -> anyhow::Result<BTreeMap<String, FileContents>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(crate_bytes));
+
+    let mut files = BTreeMap::new();
+    let mut total_size = 0;
+
+    for entry in archive.entries().context("Failed to read crate tarball")? {
+        let mut entry = entry.context("Failed to read crate tarball entry")?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = package_path(
+            &entry
+                .path()
+                .context("Failed to read crate tarball entry path")?,
+        )
+        .to_string_lossy()
+        .into_owned();
+
+        let size = entry.size();
+
+        let file = if size > max_file_size || total_size + size > max_total_size {
+            let mut hasher = Sha256::new();
+
+            io::copy(&mut entry, &mut hasher).context("Failed to read file from crate tarball")?;
+
+            FileContents {
+                sha256: hasher.finalize().to_vec(),
+                contents: None,
+            }
+        } else {
+            total_size += size;
+
+            let mut contents = Vec::with_capacity(size as usize);
+
+            entry
+                .read_to_end(&mut contents)
+                .context("Failed to read file from crate tarball")?;
+
+            FileContents {
+                sha256: Sha256::digest(&contents).to_vec(),
+                contents: Some(contents),
+            }
+        };
+
+        files.insert(path, file);
+    }
+
+    Ok(files)
+}
+
+/// Strip the leading `{name}-{version}` directory from the path of a tarball entry.
+fn package_path(entry_path: &Path) -> PathBuf {
+    entry_path.components().skip(1).collect()
+}
@@ -0,0 +1,367 @@
+//! Pull-through proxying of an upstream sparse registry.
+//!
+//! When an upstream is configured, requests for crates which aren't hosted locally are forwarded
+//! to it, so that clients only need to talk to freighter.
+//! Crates hosted locally always take precedence over upstream crates of the same name.
+//!
+//! Upstream index entries are cached for a configurable amount of time, both in memory and in the
+//! local storage provider, so that restarts don't send every request upstream again.
+//! The absence of an entry is only cached in memory.
+//! Stored entries are not put in the local index, since they aren't local crates: their versions
+//! can't be yanked or published to, and they must not shadow newer versions upstream once they
+//! expire.
+//! If the upstream can't be reached, expired stored entries are served rather than failing.
+//! Downloaded crates are verified against the checksum in the upstream index entry and stored in
+//! the local storage provider, so that later downloads are served locally.
+//!
+//! # Metrics
+//! * `upstream_requests_total`: counter of requests made to the upstream registry, labeled by
+//!   `kind` (`config`, `index`, or `download`) and `result` (`found`, `not_found` or `error`).
+
+use anyhow::{bail, Context};
+use axum::body::Bytes;
+use freighter_index::{sparse_entry_path, sparse_entry_prefix, RegistryConfig};
+use freighter_storage::{StorageError, StorageProvider};
+use lru::LruCache;
+use metrics::increment_counter;
+use reqwest::{Client, StatusCode};
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of upstream index entries to keep in memory.
+const ENTRY_CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Deserialize)]
+pub struct UpstreamConfig {
+    /// URL of the upstream sparse index, such as `https://index.crates.io/`.
+    pub index_url: String,
+    /// Number of seconds to cache upstream index entries for.
+    #[serde(default = "default_entry_ttl_secs")]
+    pub entry_ttl_secs: u64,
+}
+
+fn default_entry_ttl_secs() -> u64 {
+    300
+}
+
+/// Client for an upstream sparse registry.
+///
+/// See [the module-level docs](super::upstream) for more information.
+pub struct Upstream {
+    client: Client,
+    index_url: String,
+    entry_ttl: Duration,
+    download_url: Mutex<Option<String>>,
+    entries: Mutex<LruCache<String, CachedEntry>>,
+}
+
+struct CachedEntry {
+    fetched_at: Instant,
+    /// The raw entry, or `None` if the crate doesn't exist upstream.
+    entry: Option<Bytes>,
+}
+
+/// An upstream entry as kept in the storage provider, prefixed with the time it was fetched at.
+///
+/// The time is stored as a line of seconds since the Unix epoch, followed by the raw entry.
+struct StoredEntry {
+    fetched_at: SystemTime,
+    entry: Bytes,
+}
+
+impl StoredEntry {
+    fn encode(entry: &[u8]) -> Vec<u8> {
+        let fetched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut stored = format!("{fetched_at}\n").into_bytes();
+        stored.extend_from_slice(entry);
+
+        stored
+    }
+
+    fn decode(stored: &Bytes) -> anyhow::Result<Self> {
+        let newline = stored
+            .iter()
+            .position(|b| *b == b'\n')
+            .context("Stored upstream entry has no fetch time")?;
+
+        let fetched_at: u64 = std::str::from_utf8(&stored[..newline])
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .context("Stored upstream entry has an invalid fetch time")?;
+
+        Ok(Self {
+            fetched_at: UNIX_EPOCH + Duration::from_secs(fetched_at),
+            entry: stored.slice(newline + 1..),
+        })
+    }
+
+    /// The instant the entry was fetched at, or `None` if it is older than `ttl`.
+    fn fetched_at(&self, ttl: Duration) -> Option<Instant> {
+        let age = self.fetched_at.elapsed().unwrap_or_default();
+
+        if age >= ttl {
+            return None;
+        }
+
+        Instant::now().checked_sub(age)
+    }
+}
+
+/// The fields of an index entry line needed to verify a download.
+#[derive(Deserialize)]
+struct EntryVersion {
+    vers: Version,
+    cksum: String,
+}
+
+impl Upstream {
+    pub fn new(config: &UpstreamConfig) -> Self {
+        let mut index_url = config.index_url.clone();
+
+        if !index_url.ends_with('/') {
+            index_url.push('/');
+        }
+
+        Self {
+            client: Client::builder()
+                .user_agent(concat!("freighter/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .unwrap_or_default(),
+            index_url,
+            entry_ttl: Duration::from_secs(config.entry_ttl_secs),
+            download_url: Mutex::new(None),
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(ENTRY_CACHE_CAPACITY).unwrap(),
+            )),
+        }
+    }
+
+    /// Get the raw sparse index entry of a crate, or `None` if it doesn't exist upstream.
+    pub async fn get_entry<S>(&self, storage: &S, crate_name: &str) -> anyhow::Result<Option<Bytes>>
+    where
+        S: StorageProvider + Sync,
+    {
+        if let Some(cached) = self.entries.lock().unwrap().get(crate_name) {
+            if cached.fetched_at.elapsed() < self.entry_ttl {
+                return Ok(cached.entry.clone());
+            }
+        }
+
+        let stored = match storage.pull_upstream_entry(crate_name).await {
+            Ok(stored) => Some(StoredEntry::decode(&stored)?),
+            Err(StorageError::NotFound) => None,
+            Err(error) => {
+                tracing::warn!(?error, "Failed to retrieve stored upstream entry");
+                None
+            }
+        };
+
+        if let Some(stored) = &stored {
+            if let Some(fetched_at) = stored.fetched_at(self.entry_ttl) {
+                self.cache_entry(crate_name, fetched_at, Some(stored.entry.clone()));
+
+                return Ok(Some(stored.entry.clone()));
+            }
+        }
+
+        let url = format!("{}{}", self.index_url, sparse_entry_path(crate_name));
+
+        let entry = match self.fetch("index", &url).await {
+            Ok(entry) => entry,
+            Err(error) => match stored {
+                Some(stored) => {
+                    tracing::warn!(?error, "Serving expired upstream entry");
+                    return Ok(Some(stored.entry));
+                }
+                None => return Err(error),
+            },
+        };
+
+        if let Some(entry) = &entry {
+            // the entry can still be served even if it couldn't be stored
+            if let Err(error) = storage
+                .put_upstream_entry(crate_name, &StoredEntry::encode(entry))
+                .await
+            {
+                tracing::error!(?error, "Failed to store upstream entry");
+            }
+        }
+
+        self.cache_entry(crate_name, Instant::now(), entry.clone());
+
+        Ok(entry)
+    }
+
+    fn cache_entry(&self, crate_name: &str, fetched_at: Instant, entry: Option<Bytes>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .put(crate_name.to_string(), CachedEntry { fetched_at, entry });
+    }
+
+    /// Download a crate, or get `None` if it doesn't exist upstream.
+    ///
+    /// The crate is verified against the checksum in the upstream index entry.
+    pub async fn get_crate<S>(
+        &self,
+        storage: &S,
+        crate_name: &str,
+        version: &Version,
+    ) -> anyhow::Result<Option<Bytes>>
+    where
+        S: StorageProvider + Sync,
+    {
+        let Some(entry) = self.get_entry(storage, crate_name).await? else {
+            return Ok(None);
+        };
+
+        let mut cksum = None;
+
+        for line in entry.split(|b| *b == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let entry_version: EntryVersion =
+                serde_json::from_slice(line).context("Failed to parse upstream index entry")?;
+
+            if &entry_version.vers == version {
+                cksum = Some(entry_version.cksum);
+            }
+        }
+
+        let Some(cksum) = cksum else {
+            return Ok(None);
+        };
+
+        let url = self.download_url(crate_name, version, &cksum).await?;
+
+        let Some(crate_bytes) = self.fetch("download", &url).await? else {
+            return Ok(None);
+        };
+
+        let checksum = format!("{:x}", Sha256::digest(&crate_bytes));
+
+        if checksum != cksum {
+            bail!(
+                "Checksum of upstream crate {crate_name} {version} does not match its index entry"
+            );
+        }
+
+        Ok(Some(crate_bytes))
+    }
+
+    /// Build the download URL of a crate according to the upstream's `config.json`.
+    async fn download_url(
+        &self,
+        crate_name: &str,
+        version: &Version,
+        cksum: &str,
+    ) -> anyhow::Result<String> {
+        let cached = self.download_url.lock().unwrap().clone();
+
+        let template = match cached {
+            Some(template) => template,
+            None => {
+                let url = format!("{}config.json", self.index_url);
+
+                let config = self
+                    .fetch("config", &url)
+                    .await?
+                    .context("Upstream registry has no config.json")?;
+
+                let config: RegistryConfig = serde_json::from_slice(&config)
+                    .context("Failed to parse upstream config.json")?;
+
+                *self.download_url.lock().unwrap() = Some(config.dl.clone());
+
+                config.dl
+            }
+        };
+
+        Ok(render_download_url(&template, crate_name, version, cksum))
+    }
+
+    async fn fetch(&self, kind: &'static str, url: &str) -> anyhow::Result<Option<Bytes>> {
+        let res = self.fetch_inner(url).await;
+
+        let result = match &res {
+            Ok(Some(_)) => "found",
+            Ok(None) => "not_found",
+            Err(_) => "error",
+        };
+
+        increment_counter!("upstream_requests_total", "kind" => kind, "result" => result);
+
+        res
+    }
+
+    async fn fetch_inner(&self, url: &str) -> anyhow::Result<Option<Bytes>> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to upstream registry at {url}"))?;
+
+        // these are the statuses cargo itself treats as a missing crate
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS
+        ) {
+            return Ok(None);
+        }
+
+        let response = response
+            .error_for_status()
+            .context("Upstream registry returned an error")?;
+
+        let body = response
+            .bytes()
+            .await
+            .context("Failed to read response from upstream registry")?;
+
+        Ok(Some(body))
+    }
+}
+
+/// Build the download URL of a crate from the `dl` template of a registry's `config.json`, as
+/// cargo does.
+pub(crate) fn render_download_url(
+    template: &str,
+    crate_name: &str,
+    version: &Version,
+    cksum: &str,
+) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowercase-prefix}",
+        "{sha256-checksum}",
+    ];
+
+    if !MARKERS.iter().any(|marker| template.contains(marker)) {
+        return format!(
+            "{}/{crate_name}/{version}/download",
+            template.trim_end_matches('/')
+        );
+    }
+
+    let prefix = sparse_entry_prefix(crate_name);
+
+    template
+        .replace("{crate}", crate_name)
+        .replace("{version}", &version.to_string())
+        .replace("{prefix}", &prefix)
+        .replace("{lowercase-prefix}", &prefix.to_lowercase())
+        .replace("{sha256-checksum}", cksum)
+}
@@ -0,0 +1,174 @@
+//! Tests of upstream proxying against a stand-in registry served on a local port.
+
+use axum::body::Bytes;
+use freighter_server::upstream::{Upstream, UpstreamConfig};
+use freighter_test_support::{MemoryStorage, StandInRegistry};
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::TcpListener;
+
+const GOOD_CRATE: &[u8] = b"the contents of good 1.0.0";
+const BAD_CRATE: &[u8] = b"the contents of bad 1.0.0";
+
+/// A stand-in registry serving a `good` crate, and a `bad` crate whose contents don't match its
+/// checksum.
+struct StandIn(StandInRegistry);
+
+impl StandIn {
+    fn start() -> Self {
+        // both crates are listed with the checksum of the good one
+        let entry = |crate_name: &str| {
+            let cksum = format!("{:x}", Sha256::digest(GOOD_CRATE));
+
+            format!(
+                "{{\"name\":\"{crate_name}\",\"vers\":\"1.0.0\",\"deps\":[],\"cksum\":\"{cksum}\",\"features\":{{}},\"yanked\":false}}\n"
+            )
+        };
+
+        let entries = HashMap::from([
+            ("good".to_string(), entry("good")),
+            ("bad".to_string(), entry("bad")),
+        ]);
+
+        let crates = HashMap::from([
+            (
+                ("good".to_string(), "1.0.0".to_string()),
+                Bytes::from_static(GOOD_CRATE),
+            ),
+            (
+                ("bad".to_string(), "1.0.0".to_string()),
+                Bytes::from_static(BAD_CRATE),
+            ),
+        ]);
+
+        Self(StandInRegistry::start(entries, crates))
+    }
+
+    fn upstream(&self) -> Upstream {
+        Upstream::new(&UpstreamConfig {
+            index_url: self.0.index_url(),
+            entry_ttl_secs: 300,
+        })
+    }
+
+    fn index_requests(&self) -> usize {
+        self.0.index_requests()
+    }
+}
+
+fn v1() -> Version {
+    Version::new(1, 0, 0)
+}
+
+#[tokio::test]
+async fn upstream_entry_is_proxied_and_stored() {
+    let stand_in = StandIn::start();
+    let storage = MemoryStorage::default();
+
+    let entry = stand_in
+        .upstream()
+        .get_entry(&storage, "good")
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(entry.starts_with(b"{\"name\":\"good\""));
+    assert_eq!(stand_in.index_requests(), 1);
+
+    // a fresh client, such as after a restart, is served from storage
+    let stored = stand_in
+        .upstream()
+        .get_entry(&storage, "good")
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(stored, entry);
+    assert_eq!(stand_in.index_requests(), 1);
+}
+
+#[tokio::test]
+async fn missing_upstream_entry_is_not_found() {
+    let stand_in = StandIn::start();
+    let storage = MemoryStorage::default();
+
+    let entry = stand_in
+        .upstream()
+        .get_entry(&storage, "missing")
+        .await
+        .unwrap();
+
+    assert!(entry.is_none());
+    assert!(storage.keys().is_empty());
+}
+
+#[tokio::test]
+async fn upstream_crate_with_good_checksum_is_downloaded() {
+    let stand_in = StandIn::start();
+    let storage = MemoryStorage::default();
+
+    let crate_bytes = stand_in
+        .upstream()
+        .get_crate(&storage, "good", &v1())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(crate_bytes, GOOD_CRATE);
+
+    let missing_version = stand_in
+        .upstream()
+        .get_crate(&storage, "good", &Version::new(2, 0, 0))
+        .await
+        .unwrap();
+
+    assert!(missing_version.is_none());
+}
+
+#[tokio::test]
+async fn upstream_crate_with_bad_checksum_is_rejected() {
+    let stand_in = StandIn::start();
+    let storage = MemoryStorage::default();
+
+    let error = stand_in
+        .upstream()
+        .get_crate(&storage, "bad", &v1())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("Checksum"), "{error}");
+}
+
+#[tokio::test]
+async fn stored_entry_is_served_while_upstream_is_unreachable() {
+    let stand_in = StandIn::start();
+    let storage = MemoryStorage::default();
+
+    let entry = stand_in
+        .upstream()
+        .get_entry(&storage, "good")
+        .await
+        .unwrap()
+        .unwrap();
+
+    // nothing listens on a port which was bound and released
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let unreachable = Upstream::new(&UpstreamConfig {
+        index_url: format!("http://{closed}/"),
+        entry_ttl_secs: 0,
+    });
+
+    let stored = unreachable
+        .get_entry(&storage, "good")
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(stored, entry);
+    assert!(unreachable.get_entry(&storage, "other").await.is_err());
+}
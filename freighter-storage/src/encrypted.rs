@@ -0,0 +1,436 @@
+//! Storage wrapper which encrypts crates before handing them to another [`StorageProvider`].
+//!
+//! Crates are sealed with XChaCha20-Poly1305, using a fresh random nonce for every object.
+//! The crate name and version are bound to each object as associated data, so an object copied
+//! over the key of a different crate or version will fail to decrypt rather than being served.
+//! Documentation files are sealed the same way, additionally binding the path of the file.
+//!
+//! Every stored object starts with a small header identifying the format and the key it was
+//! encrypted with:
+//!
+//! ```text
+//! | magic (4 bytes, "FRE1") | key id (4 bytes, big endian) | nonce (24 bytes) | ciphertext |
+//! ```
+//!
+//! # Key files
+//! Keys are loaded from a local file containing one key per line, in the form `<id> <hex key>`,
+//! where `id` is an unsigned 32-bit integer and the key is 32 bytes of hex.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! The first key in the file is used to encrypt new crates, while every key in the file can be
+//! used to decrypt.
+//! To rotate keys, add a new key to the top of the file and keep the old ones around until all
+//! objects encrypted with them have been re-uploaded.
+
+use crate::{StorageProvider, StorageResult};
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"FRE1";
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+
+/// A set of keys used to encrypt and decrypt crates, indexed by key id.
+///
+/// See [the module-level docs](super::encrypted) for the key file format.
+#[derive(Clone)]
+pub struct KeyRing {
+    active: u32,
+    keys: Arc<HashMap<u32, XChaCha20Poly1305>>,
+}
+
+impl KeyRing {
+    /// Load a key ring from a key file on disk.
+    pub fn from_file(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let path = path.as_ref();
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?;
+
+        Self::parse(&contents)
+    }
+
+    /// Parse a key ring from the contents of a key file.
+    pub fn parse(contents: &str) -> StorageResult<Self> {
+        let mut active = None;
+        let mut keys = HashMap::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (id, key) = parse_key_line(line)
+                .with_context(|| format!("Invalid key on line {} of key file", line_number + 1))?;
+
+            if keys.insert(id, key).is_some() {
+                return Err(anyhow!("Duplicate key id {id} in key file").into());
+            }
+
+            active.get_or_insert(id);
+        }
+
+        let active = active.context("Key file does not contain any keys")?;
+
+        Ok(Self {
+            active,
+            keys: Arc::new(keys),
+        })
+    }
+
+    /// The id of the key used to encrypt new crates.
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+}
+
+fn parse_key_line(line: &str) -> anyhow::Result<(u32, XChaCha20Poly1305)> {
+    let Some((id, key)) = line.split_once(char::is_whitespace) else {
+        bail!("Expected a key id and a hex encoded key separated by whitespace");
+    };
+
+    let id = id
+        .parse()
+        .context("Key id is not an unsigned 32-bit integer")?;
+    let key = hex::decode(key.trim()).context("Key is not valid hex")?;
+    let cipher = XChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|_| anyhow!("Key must be exactly 32 bytes long"))?;
+
+    Ok((id, cipher))
+}
+
+/// Storage provider which transparently encrypts crates stored in an inner provider.
+///
+/// See [the module-level docs](super::encrypted) for more information.
+#[derive(Clone)]
+pub struct EncryptedStorageProvider<S> {
+    inner: S,
+    keys: KeyRing,
+}
+
+impl<S> EncryptedStorageProvider<S> {
+    /// Wrap a storage provider, encrypting and decrypting crates with the given keys.
+    pub fn new(inner: S, keys: KeyRing) -> Self {
+        Self { inner, keys }
+    }
+
+    fn seal(&self, aad: &str, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let cipher = &self.keys.keys[&self.keys.active];
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt object"))?;
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&self.keys.active.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    fn open(&self, aad: &str, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if sealed.len() < HEADER_LEN || &sealed[..MAGIC.len()] != MAGIC {
+            bail!("Stored object is not encrypted");
+        }
+
+        let (key_id, rest) = sealed[MAGIC.len()..].split_at(KEY_ID_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let key_id = u32::from_be_bytes(key_id.try_into().unwrap());
+
+        let cipher = self
+            .keys
+            .keys
+            .get(&key_id)
+            .with_context(|| format!("Object was encrypted with unknown key id {key_id}"))?;
+
+        cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt object, it may have been tampered with"))
+    }
+}
+
+#[async_trait]
+impl<S> StorageProvider for EncryptedStorageProvider<S>
+where
+    S: StorageProvider + Send + Sync,
+{
+    async fn pull_crate(&self, name: &str, version: &str) -> StorageResult<Bytes> {
+        let sealed = self.inner.pull_crate(name, version).await?;
+
+        let crate_bytes = self.open(&associated_data(name, version), &sealed)?;
+
+        Ok(Bytes::from(crate_bytes))
+    }
+
+    async fn put_crate(&self, name: &str, version: &str, crate_bytes: &[u8]) -> StorageResult<()> {
+        let sealed = self.seal(&associated_data(name, version), crate_bytes)?;
+
+        self.inner.put_crate(name, version, &sealed).await
+    }
+
+    async fn pull_docs_file(&self, name: &str, version: &str, path: &str) -> StorageResult<Bytes> {
+        let sealed = self.inner.pull_docs_file(name, version, path).await?;
+
+        let file_bytes = self.open(&docs_associated_data(name, version, path), &sealed)?;
+
+        Ok(Bytes::from(file_bytes))
+    }
+
+    async fn put_docs_file(
+        &self,
+        name: &str,
+        version: &str,
+        path: &str,
+        file_bytes: &[u8],
+    ) -> StorageResult<()> {
+        let sealed = self.seal(&docs_associated_data(name, version, path), file_bytes)?;
+
+        self.inner.put_docs_file(name, version, path, &sealed).await
+    }
+
+    async fn pull_upstream_entry(&self, name: &str) -> StorageResult<Bytes> {
+        let sealed = self.inner.pull_upstream_entry(name).await?;
+
+        let entry_bytes = self.open(&upstream_entry_associated_data(name), &sealed)?;
+
+        Ok(Bytes::from(entry_bytes))
+    }
+
+    async fn put_upstream_entry(&self, name: &str, entry_bytes: &[u8]) -> StorageResult<()> {
+        let sealed = self.seal(&upstream_entry_associated_data(name), entry_bytes)?;
+
+        self.inner.put_upstream_entry(name, &sealed).await
+    }
+}
+
+#[inline(always)]
+fn associated_data(name: &str, version: &str) -> String {
+    format!("{name}-{version}")
+}
+
+#[inline(always)]
+fn docs_associated_data(name: &str, version: &str, path: &str) -> String {
+    format!("docs/{name}-{version}/{path}")
+}
+
+#[inline(always)]
+fn upstream_entry_associated_data(name: &str) -> String {
+    format!("upstream-index/{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageError;
+
+    const KEY_1: &str = "1 0101010101010101010101010101010101010101010101010101010101010101";
+    const KEY_2: &str = "2 0202020202020202020202020202020202020202020202020202020202020202";
+
+    fn provider(key_file: &str) -> EncryptedStorageProvider<()> {
+        EncryptedStorageProvider::new((), KeyRing::parse(key_file).unwrap())
+    }
+
+    /// The message of the error parsing a key file, with its causes.
+    fn parse_error(key_file: &str) -> String {
+        match KeyRing::parse(key_file) {
+            Ok(_) => panic!("Parsed invalid key file {key_file:?}"),
+            Err(StorageError::ServiceError(error)) => format!("{error:#}"),
+            Err(error) => panic!("Unexpected error {error}"),
+        }
+    }
+
+    fn open_error(provider: &EncryptedStorageProvider<()>, aad: &str, sealed: &[u8]) -> String {
+        provider.open(aad, sealed).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn sealed_objects_open() {
+        let provider = provider(KEY_1);
+        let aad = associated_data("foo", "1.0.0");
+
+        let sealed = provider.seal(&aad, b"crate contents").unwrap();
+
+        assert_eq!(&sealed[..MAGIC.len()], MAGIC);
+        assert!(!sealed.windows(14).any(|window| window == b"crate contents"));
+        assert_eq!(provider.open(&aad, &sealed).unwrap(), b"crate contents");
+
+        // every object gets a fresh nonce
+        assert_ne!(provider.seal(&aad, b"crate contents").unwrap(), sealed);
+    }
+
+    #[test]
+    fn objects_sealed_with_older_keys_open_after_rotation() {
+        let aad = associated_data("foo", "1.0.0");
+
+        let sealed = provider(KEY_1).seal(&aad, b"crate contents").unwrap();
+
+        let rotated = provider(&format!("{KEY_2}\n{KEY_1}"));
+
+        assert_eq!(rotated.keys.active_key_id(), 2);
+        assert_eq!(rotated.open(&aad, &sealed).unwrap(), b"crate contents");
+
+        let resealed = rotated.seal(&aad, b"crate contents").unwrap();
+
+        assert_eq!(
+            resealed[MAGIC.len()..HEADER_LEN - NONCE_LEN],
+            2u32.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn objects_sealed_with_unknown_keys_fail_to_open() {
+        let aad = associated_data("foo", "1.0.0");
+
+        let sealed = provider(KEY_1).seal(&aad, b"crate contents").unwrap();
+
+        assert_eq!(
+            open_error(&provider(KEY_2), &aad, &sealed),
+            "Object was encrypted with unknown key id 1"
+        );
+    }
+
+    #[test]
+    fn tampered_objects_fail_to_open() {
+        let provider = provider(KEY_1);
+        let aad = associated_data("foo", "1.0.0");
+
+        let sealed = provider.seal(&aad, b"crate contents").unwrap();
+
+        // the last byte of the ciphertext, and the first byte of the nonce
+        for index in [sealed.len() - 1, MAGIC.len() + KEY_ID_LEN] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+
+            assert!(
+                open_error(&provider, &aad, &tampered).contains("tampered"),
+                "byte {index}"
+            );
+        }
+
+        let mut tampered = sealed.clone();
+        tampered[0] = b'X';
+
+        assert_eq!(
+            open_error(&provider, &aad, &tampered),
+            "Stored object is not encrypted"
+        );
+
+        assert_eq!(
+            open_error(&provider, &aad, &sealed[..HEADER_LEN - 1]),
+            "Stored object is not encrypted"
+        );
+    }
+
+    #[test]
+    fn objects_fail_to_open_under_other_names() {
+        let provider = provider(KEY_1);
+
+        let sealed = provider
+            .seal(&associated_data("foo", "1.0.0"), b"crate contents")
+            .unwrap();
+
+        for aad in [
+            associated_data("bar", "1.0.0"),
+            associated_data("foo", "1.0.1"),
+            docs_associated_data("foo", "1.0.0", "index.html"),
+            upstream_entry_associated_data("foo"),
+        ] {
+            assert!(
+                open_error(&provider, &aad, &sealed).contains("tampered"),
+                "{aad}"
+            );
+        }
+
+        let docs = provider
+            .seal(
+                &docs_associated_data("foo", "1.0.0", "index.html"),
+                b"<html>",
+            )
+            .unwrap();
+
+        assert!(open_error(
+            &provider,
+            &docs_associated_data("foo", "1.0.0", "other.html"),
+            &docs
+        )
+        .contains("tampered"));
+    }
+
+    #[test]
+    fn key_files_skip_comments_and_empty_lines() {
+        let keys =
+            KeyRing::parse(&format!("# rotated in\n\n  {KEY_2}  \n# old\n{KEY_1}\n")).unwrap();
+
+        assert_eq!(keys.active_key_id(), 2);
+        assert_eq!(keys.keys.len(), 2);
+    }
+
+    #[test]
+    fn malformed_key_files_are_rejected() {
+        let short = "1 0101";
+        let long = format!("{KEY_1}01");
+
+        for (key_file, message) in [
+            ("", "Key file does not contain any keys"),
+            ("# no keys\n", "Key file does not contain any keys"),
+            ("1", "separated by whitespace"),
+            (
+                "x 0101010101010101010101010101010101010101010101010101010101010101",
+                "unsigned 32-bit integer",
+            ),
+            (
+                "-1 0101010101010101010101010101010101010101010101010101010101010101",
+                "unsigned 32-bit integer",
+            ),
+            (
+                "1 zz01010101010101010101010101010101010101010101010101010101010101",
+                "not valid hex",
+            ),
+            (short, "exactly 32 bytes"),
+            (&long, "exactly 32 bytes"),
+        ] {
+            let error = parse_error(key_file);
+
+            assert!(error.contains(message), "{key_file:?}: {error}");
+        }
+
+        // errors point at the offending line
+        assert!(parse_error(&format!("{KEY_1}\n\n1")).contains("line 3"));
+    }
+
+    #[test]
+    fn duplicate_key_ids_are_rejected() {
+        let other_key_1 = "1 0303030303030303030303030303030303030303030303030303030303030303";
+
+        assert_eq!(
+            parse_error(&format!("{KEY_1}\n{KEY_2}\n{other_key_1}")),
+            "Duplicate key id 1 in key file"
+        );
+    }
+}
@@ -4,6 +4,9 @@ use bytes::Bytes;
 #[cfg(feature = "s3-backend")]
 pub mod s3_client;
 
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+
 mod error;
 
 pub use error::*;
@@ -12,4 +15,19 @@ pub use error::*;
 pub trait StorageProvider {
     async fn pull_crate(&self, name: &str, version: &str) -> StorageResult<Bytes>;
     async fn put_crate(&self, name: &str, version: &str, crate_bytes: &[u8]) -> StorageResult<()>;
+    /// Get a file from the documentation of a crate version, by its path within the docs.
+    async fn pull_docs_file(&self, name: &str, version: &str, path: &str) -> StorageResult<Bytes>;
+    /// Store a file of the documentation of a crate version, replacing any existing file.
+    async fn put_docs_file(
+        &self,
+        name: &str,
+        version: &str,
+        path: &str,
+        file_bytes: &[u8],
+    ) -> StorageResult<()>;
+    /// Get the stored copy of a crate's sparse index entry from an upstream registry.
+    async fn pull_upstream_entry(&self, name: &str) -> StorageResult<Bytes>;
+    /// Store a copy of a crate's sparse index entry from an upstream registry, replacing any
+    /// existing copy.
+    async fn put_upstream_entry(&self, name: &str, entry_bytes: &[u8]) -> StorageResult<()>;
 }
@@ -19,12 +19,52 @@
 use crate::{StorageError, StorageProvider, StorageResult};
 use anyhow::Context;
 use async_trait::async_trait;
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_credential_types::provider::SharedCredentialsProvider;
 use aws_credential_types::Credentials;
 use aws_sdk_s3::config::{AppName, Config, Region};
 use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::primitives::ByteStream;
 use bytes::Bytes;
 
+/// Connection parameters for [`S3StorageProvider`].
+pub struct S3Config {
+    /// Name of the bucket to store crates in.
+    pub bucket_name: String,
+    /// Endpoint of the S3-compatible service.
+    ///
+    /// If not set, the regional AWS endpoint is used.
+    pub endpoint_url: Option<String>,
+    /// Region the bucket lives in.
+    pub region: String,
+    /// Address the bucket by path (`{endpoint}/{bucket}/{key}`) rather than by virtual host
+    /// (`{bucket}.{endpoint}/{key}`).
+    ///
+    /// Most self-hosted S3-compatible services, such as MinIO, require this.
+    pub path_style: bool,
+    /// Prefix to place in front of every object key, allowing several registries to share a
+    /// bucket.
+    pub key_prefix: Option<String>,
+    /// Credentials used to authenticate with the service.
+    pub credentials: S3Credentials,
+}
+
+/// Source of credentials for [`S3StorageProvider`].
+pub enum S3Credentials {
+    /// A fixed set of credentials.
+    Static {
+        access_key_id: String,
+        secret_access_key: String,
+        /// Session token for temporary credentials.
+        session_token: Option<String>,
+    },
+    /// The standard AWS credential chain.
+    ///
+    /// This checks environment variables, the shared config and credentials files, web identity
+    /// tokens, ECS container credentials, and finally the EC2 instance metadata service.
+    DefaultChain,
+}
+
 /// Storage client for working with S3-compatible APIs.
 ///
 /// See [the module-level docs](super::s3_client) for more information.
@@ -32,11 +72,13 @@ use bytes::Bytes;
 pub struct S3StorageProvider {
     client: aws_sdk_s3::Client,
     bucket_name: String,
+    key_prefix: String,
 }
 
 impl S3StorageProvider {
-    /// Construct a new client, returning an error if the information could not be used to
-    /// communicate with a valid bucket.
+    /// Construct a new client using static credentials and virtual-host addressing.
+    ///
+    /// See [`S3StorageProvider::from_config`] for more options.
     pub fn new(
         bucket_name: &str,
         endpoint_url: &str,
@@ -44,33 +86,115 @@ impl S3StorageProvider {
         access_key: &str,
         secret_key: &str,
     ) -> Self {
-        let config = Config::builder()
+        let credentials = Credentials::from_keys(access_key, secret_key, None);
+
+        Self::with_credentials_provider(
+            bucket_name,
+            Some(endpoint_url),
+            region,
+            false,
+            None,
+            SharedCredentialsProvider::new(credentials),
+        )
+    }
+
+    /// Construct a new client from the provided configuration.
+    ///
+    /// This is async because resolving the default credential chain may need to do IO.
+    pub async fn from_config(config: S3Config) -> Self {
+        let credentials = match config.credentials {
+            S3Credentials::Static {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            } => SharedCredentialsProvider::new(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                session_token,
+                None,
+                "freighter-config",
+            )),
+            S3Credentials::DefaultChain => SharedCredentialsProvider::new(
+                DefaultCredentialsChain::builder()
+                    .region(Region::new(config.region.clone()))
+                    .build()
+                    .await,
+            ),
+        };
+
+        Self::with_credentials_provider(
+            &config.bucket_name,
+            config.endpoint_url.as_deref(),
+            &config.region,
+            config.path_style,
+            config.key_prefix.as_deref(),
+            credentials,
+        )
+    }
+
+    fn with_credentials_provider(
+        bucket_name: &str,
+        endpoint_url: Option<&str>,
+        region: &str,
+        path_style: bool,
+        key_prefix: Option<&str>,
+        credentials: SharedCredentialsProvider,
+    ) -> Self {
+        let mut builder = Config::builder()
             .region(Region::new(region.to_string()))
-            .endpoint_url(endpoint_url)
-            .credentials_provider(Credentials::from_keys(access_key, secret_key, None))
-            .app_name(AppName::new("freighter".to_string()).unwrap())
-            .build();
+            .force_path_style(path_style)
+            .credentials_provider(credentials)
+            .app_name(AppName::new("freighter".to_string()).unwrap());
+
+        if let Some(endpoint_url) = endpoint_url {
+            builder = builder.endpoint_url(endpoint_url);
+        }
 
         let bucket_name = bucket_name.to_string();
-        let client = aws_sdk_s3::Client::from_conf(config);
+        let client = aws_sdk_s3::Client::from_conf(builder.build());
+
+        // normalize the prefix so that we can simply prepend it to keys
+        let key_prefix = match key_prefix.map(|p| p.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => format!("{prefix}/"),
+            _ => String::new(),
+        };
 
         Self {
             client,
             bucket_name,
+            key_prefix,
         }
     }
-}
 
-#[async_trait]
-impl StorageProvider for S3StorageProvider {
-    async fn pull_crate(&self, name: &str, version: &str) -> StorageResult<Bytes> {
-        let path = construct_path(name, version);
+    /// Store a file under the key prefix, replacing any existing object.
+    ///
+    /// This is for files which aren't part of a crate version, such as an exported sparse index.
+    pub async fn put_file(&self, path: &str, file_bytes: &[u8]) -> StorageResult<()> {
+        self.put_object(format!("{}{path}", self.key_prefix), file_bytes, "file")
+            .await
+    }
+
+    #[inline(always)]
+    fn construct_path(&self, name: &str, version: &str) -> String {
+        format!("{}{name}-{version}.crate", self.key_prefix)
+    }
 
+    #[inline(always)]
+    fn construct_docs_path(&self, name: &str, version: &str, path: &str) -> String {
+        format!("{}docs/{name}/{version}/{path}", self.key_prefix)
+    }
+
+    #[inline(always)]
+    fn construct_upstream_entry_path(&self, name: &str) -> String {
+        format!("{}upstream-index/{name}", self.key_prefix)
+    }
+
+    async fn pull_object(&self, key: String, what: &str) -> StorageResult<Bytes> {
         let resp = self
             .client
             .get_object()
             .bucket(self.bucket_name.clone())
-            .key(path)
+            .key(key)
             .send()
             .await;
 
@@ -81,35 +205,75 @@ impl StorageProvider for S3StorageProvider {
             }
         }
 
-        let data = resp.context("Failed to retrieve crate")?;
+        let data = resp.with_context(|| format!("Failed to retrieve {what}"))?;
 
-        let crate_bytes = data
+        let bytes = data
             .body
             .collect()
             .await
-            .context("Failed to retrieve body of crate")?
+            .with_context(|| format!("Failed to retrieve body of {what}"))?
             .into_bytes();
 
-        Ok(crate_bytes)
+        Ok(bytes)
     }
 
-    async fn put_crate(&self, name: &str, version: &str, crate_bytes: &[u8]) -> StorageResult<()> {
-        let path = construct_path(name, version);
-
+    async fn put_object(&self, key: String, bytes: &[u8], what: &str) -> StorageResult<()> {
         self.client
             .put_object()
             .bucket(self.bucket_name.clone())
-            .key(path)
-            .body(ByteStream::from(crate_bytes.to_vec()))
+            .key(key)
+            .body(ByteStream::from(bytes.to_vec()))
             .send()
             .await
-            .context("Failed to put crate in bucket")?;
+            .with_context(|| format!("Failed to put {what} in bucket"))?;
 
         Ok(())
     }
 }
 
-#[inline(always)]
-fn construct_path(name: &str, version: &str) -> String {
-    format!("{name}-{version}.crate")
+#[async_trait]
+impl StorageProvider for S3StorageProvider {
+    async fn pull_crate(&self, name: &str, version: &str) -> StorageResult<Bytes> {
+        self.pull_object(self.construct_path(name, version), "crate")
+            .await
+    }
+
+    async fn put_crate(&self, name: &str, version: &str, crate_bytes: &[u8]) -> StorageResult<()> {
+        self.put_object(self.construct_path(name, version), crate_bytes, "crate")
+            .await
+    }
+
+    async fn pull_docs_file(&self, name: &str, version: &str, path: &str) -> StorageResult<Bytes> {
+        self.pull_object(self.construct_docs_path(name, version, path), "docs file")
+            .await
+    }
+
+    async fn put_docs_file(
+        &self,
+        name: &str,
+        version: &str,
+        path: &str,
+        file_bytes: &[u8],
+    ) -> StorageResult<()> {
+        self.put_object(
+            self.construct_docs_path(name, version, path),
+            file_bytes,
+            "docs file",
+        )
+        .await
+    }
+
+    async fn pull_upstream_entry(&self, name: &str) -> StorageResult<Bytes> {
+        self.pull_object(self.construct_upstream_entry_path(name), "upstream entry")
+            .await
+    }
+
+    async fn put_upstream_entry(&self, name: &str, entry_bytes: &[u8]) -> StorageResult<()> {
+        self.put_object(
+            self.construct_upstream_entry_path(name),
+            entry_bytes,
+            "upstream entry",
+        )
+        .await
+    }
 }
@@ -0,0 +1,193 @@
+//! Fixtures shared by the tests of the freighter crates.
+//!
+//! Tests which need a Postgres database are marked `#[ignore]`, and are run with
+//! `cargo test -- --ignored` once one is configured as described in [`index_db_config`].
+
+use async_trait::async_trait;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use bytes::Bytes;
+use freighter_storage::{StorageError, StorageProvider, StorageResult};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The configuration of the index database to test against.
+///
+/// This needs a database initialized with `sql/init-index-db.sql`, configured like `index_db` in
+/// the config file through the `FREIGHTER_TEST_INDEX_DB` environment variable, such as
+/// `{host: localhost, user: postgres, dbname: freighter}`.
+pub fn index_db_config() -> deadpool_postgres::Config {
+    let config = std::env::var("FREIGHTER_TEST_INDEX_DB")
+        .expect("FREIGHTER_TEST_INDEX_DB must be set to run database tests");
+
+    serde_yaml::from_str(&config).expect("Invalid FREIGHTER_TEST_INDEX_DB")
+}
+
+/// A name which hasn't been used before, since the database is shared between runs.
+pub fn unique_name(prefix: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    format!("{prefix}-{nanos}")
+}
+
+/// Storage provider keeping everything in memory, which is shared between clones.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<HashMap<String, Bytes>>>,
+}
+
+impl MemoryStorage {
+    /// The keys of every stored file, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.files.lock().unwrap().keys().cloned().collect();
+
+        keys.sort();
+
+        keys
+    }
+
+    fn pull(&self, key: String) -> StorageResult<Bytes> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .ok_or(StorageError::NotFound)
+    }
+
+    fn put(&self, key: String, bytes: &[u8]) -> StorageResult<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(key, Bytes::copy_from_slice(bytes));
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageProvider for MemoryStorage {
+    async fn pull_crate(&self, name: &str, version: &str) -> StorageResult<Bytes> {
+        self.pull(format!("{name}-{version}.crate"))
+    }
+
+    async fn put_crate(&self, name: &str, version: &str, bytes: &[u8]) -> StorageResult<()> {
+        self.put(format!("{name}-{version}.crate"), bytes)
+    }
+
+    async fn pull_docs_file(&self, name: &str, version: &str, path: &str) -> StorageResult<Bytes> {
+        self.pull(format!("docs/{name}/{version}/{path}"))
+    }
+
+    async fn put_docs_file(
+        &self,
+        name: &str,
+        version: &str,
+        path: &str,
+        bytes: &[u8],
+    ) -> StorageResult<()> {
+        self.put(format!("docs/{name}/{version}/{path}"), bytes)
+    }
+
+    async fn pull_upstream_entry(&self, name: &str) -> StorageResult<Bytes> {
+        self.pull(format!("upstream-index/{name}"))
+    }
+
+    async fn put_upstream_entry(&self, name: &str, bytes: &[u8]) -> StorageResult<()> {
+        self.put(format!("upstream-index/{name}"), bytes)
+    }
+}
+
+/// Contents served by a [`StandInRegistry`].
+struct Contents {
+    entries: HashMap<String, String>,
+    crates: HashMap<(String, String), Bytes>,
+    index_requests: AtomicUsize,
+}
+
+/// A stand-in for a sparse registry, served on a local port.
+pub struct StandInRegistry {
+    address: SocketAddr,
+    contents: Arc<Contents>,
+}
+
+impl StandInRegistry {
+    /// Serve the sparse index `entries`, by crate name, and the `.crate` files in `crates`, by
+    /// crate name and version.
+    pub fn start(
+        entries: HashMap<String, String>,
+        crates: HashMap<(String, String), Bytes>,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let config = json!({
+            "dl": format!("http://{address}/dl/{{crate}}/{{version}}/download"),
+            "api": format!("http://{address}"),
+        })
+        .to_string();
+
+        let contents = Arc::new(Contents {
+            entries,
+            crates,
+            index_requests: AtomicUsize::new(0),
+        });
+
+        let router = Router::new()
+            .route("/config.json", get(move || async move { config }))
+            .route("/:prefix_1/:prefix_2/:crate_name", get(entry))
+            .route("/dl/:crate_name/:version/download", get(download))
+            .with_state(contents.clone());
+
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+
+        tokio::spawn(server);
+
+        Self { address, contents }
+    }
+
+    /// The URL of the sparse index.
+    pub fn index_url(&self) -> String {
+        format!("http://{}/", self.address)
+    }
+
+    /// The number of index entries requested so far.
+    pub fn index_requests(&self) -> usize {
+        self.contents.index_requests.load(Ordering::SeqCst)
+    }
+}
+
+async fn entry(
+    State(contents): State<Arc<Contents>>,
+    Path((_, _, crate_name)): Path<(String, String, String)>,
+) -> Result<String, StatusCode> {
+    contents.index_requests.fetch_add(1, Ordering::SeqCst);
+
+    contents
+        .entries
+        .get(&crate_name)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn download(
+    State(contents): State<Arc<Contents>>,
+    Path(key): Path<(String, String)>,
+) -> Result<Bytes, StatusCode> {
+    contents
+        .crates
+        .get(&key)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
@@ -0,0 +1,236 @@
+//! Export of crates and their dependencies as a cargo local registry.
+//!
+//! The dependency closure of the requested crates is resolved from the index, as described in
+//! [the closure module](crate::closure), and written out in the layout of cargo's
+//! `local-registry` sources: an `index` directory holding an entry for every crate, laid out like
+//! a sparse index, next to the `.crate` files named `{name}-{version}.crate`.
+//! Index entries only list the versions included in the bundle.
+//!
+//! The bundle can either be written to a directory, or to a gzipped tarball which should be
+//! extracted into a directory before use.
+//! Cargo can then be pointed at that directory by replacing the source of the registry the crates
+//! are depended on from, named as in `[registries]` and with the same index URL:
+//!
+//! ```toml
+//! [source.my-registry]
+//! registry = "sparse+https://freighter.example.com/index/"
+//! replace-with = "bundle"
+//!
+//! [source.bundle]
+//! local-registry = "/path/to/bundle"
+//! ```
+//!
+//! Bundled crates which are depended on from crates.io, such as mirrored ones, are covered by
+//! replacing `[source.crates-io]` with the bundle too.
+
+use crate::closure::{resolve_closure, CrateSpec};
+use anyhow::{bail, Context};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use freighter_index::{
+    render_sparse_entry, sparse_entry_path, CrateVersion, IndexError, IndexProvider,
+};
+use freighter_storage::StorageProvider;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct BundleSummary {
+    /// The number of crates in the bundle.
+    pub crates: usize,
+    /// The number of crate versions in the bundle.
+    pub versions: usize,
+}
+
+/// Write the crates matching `specs`, along with their dependencies, to a bundle at `out`.
+///
+/// If `tarball` is set, `out` is the path of the tarball to create, and otherwise it is the
+/// directory to write the bundle to.
+pub async fn export_bundle<I, S>(
+    index: &I,
+    storage: &S,
+    specs: &[CrateSpec],
+    out: &Path,
+    tarball: bool,
+) -> anyhow::Result<BundleSummary>
+where
+    I: IndexProvider + Sync,
+    S: StorageProvider + Sync,
+{
+    let closure = resolve_closure(specs, |crate_name| async move {
+        match index.get_sparse_entry(&crate_name).await {
+            Ok(versions) => Ok(versions),
+            Err(IndexError::NotFound) => bail!("Index has no crate named {crate_name}"),
+            Err(error) => {
+                Err(error).with_context(|| format!("Failed to fetch index entry for {crate_name}"))
+            }
+        }
+    })
+    .await?;
+
+    let mut writer = if tarball {
+        // the tarball is moved into place once complete, and removed if the export fails, so a
+        // failed export doesn't leave a truncated tarball behind
+        let mut tmp = out.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let file =
+            File::create(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+
+        BundleWriter::Tarball {
+            builder: tar::Builder::new(GzEncoder::new(file, Compression::default())),
+            tmp,
+            out: out.to_path_buf(),
+        }
+    } else {
+        BundleWriter::Directory(out.to_path_buf())
+    };
+
+    let crates = match write_closure(storage, &closure, &mut writer).await {
+        Ok(crates) => crates,
+        Err(error) => {
+            writer.discard();
+
+            return Err(error);
+        }
+    };
+
+    writer.finish()?;
+
+    Ok(BundleSummary {
+        crates,
+        versions: closure.len(),
+    })
+}
+
+/// Write every version in the closure, and the index entries listing them, returning the number
+/// of crates written.
+async fn write_closure<S>(
+    storage: &S,
+    closure: &BTreeMap<(String, Version), CrateVersion>,
+    writer: &mut BundleWriter,
+) -> anyhow::Result<usize>
+where
+    S: StorageProvider + Sync,
+{
+    let mut crates: BTreeMap<&str, Vec<CrateVersion>> = BTreeMap::new();
+
+    for version in closure.values() {
+        let vers = version.vers.to_string();
+
+        let crate_bytes = storage
+            .pull_crate(&version.name, &vers)
+            .await
+            .with_context(|| format!("Failed to fetch crate {} {vers}", version.name))?;
+
+        if format!("{:x}", Sha256::digest(&crate_bytes)) != version.cksum {
+            bail!(
+                "Stored crate {} {vers} does not match the checksum in the index",
+                version.name
+            );
+        }
+
+        writer.add(&format!("{}-{vers}.crate", version.name), &crate_bytes)?;
+
+        crates
+            .entry(&version.name)
+            .or_default()
+            .push(version.clone());
+    }
+
+    for (crate_name, versions) in &crates {
+        let entry = render_sparse_entry(versions).context("Failed to render index entry")?;
+
+        writer.add(
+            &format!("index/{}", sparse_entry_path(crate_name)),
+            entry.as_bytes(),
+        )?;
+    }
+
+    Ok(crates.len())
+}
+
+enum BundleWriter {
+    Directory(PathBuf),
+    Tarball {
+        builder: tar::Builder<GzEncoder<File>>,
+        tmp: PathBuf,
+        out: PathBuf,
+    },
+}
+
+impl BundleWriter {
+    fn add(&mut self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        match self {
+            BundleWriter::Directory(dir) => {
+                let path = dir.join(path);
+
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).with_context(|| {
+                        format!("Failed to create directory {}", parent.display())
+                    })?;
+                }
+
+                fs::write(&path, contents)
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            BundleWriter::Tarball { builder, .. } => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+
+                builder
+                    .append_data(&mut header, path, contents)
+                    .with_context(|| format!("Failed to add {path} to tarball"))
+            }
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        if let BundleWriter::Tarball { builder, tmp, out } = self {
+            let result = builder
+                .into_inner()
+                .context("Failed to finish tarball")
+                .and_then(|encoder| {
+                    encoder
+                        .finish()
+                        .context("Failed to finish compressing tarball")
+                })
+                .and_then(|_| {
+                    fs::rename(&tmp, &out)
+                        .with_context(|| format!("Failed to move tarball to {}", out.display()))
+                });
+
+            if result.is_err() {
+                remove_tmp(&tmp);
+            }
+
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Abandon a partially written bundle, removing the temporary tarball if there is one.
+    ///
+    /// Files already written to a directory are left in place.
+    fn discard(self) {
+        if let BundleWriter::Tarball { builder, tmp, .. } = self {
+            // the file has to be closed before it can be removed on some platforms
+            drop(builder);
+
+            remove_tmp(&tmp);
+        }
+    }
+}
+
+fn remove_tmp(tmp: &Path) {
+    if let Err(error) = fs::remove_file(tmp) {
+        tracing::warn!(?error, "Failed to remove {}", tmp.display());
+    }
+}
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -7,4 +7,77 @@ pub struct FreighterArgs {
     /// Path to the config file.
     #[arg(short, long)]
     pub config: PathBuf,
+
+    /// Command to run, defaults to running the server.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Export the index as a static sparse index.
+    ExportIndex {
+        /// Directory to write the index into.
+        #[arg(required_unless_present = "bucket")]
+        out_dir: Option<PathBuf>,
+        /// Bucket to write the index into instead of a directory.
+        ///
+        /// The bucket is accessed with the same endpoint, region, and credentials as the crate
+        /// store.
+        #[arg(long, conflicts_with = "out_dir")]
+        bucket: Option<String>,
+        /// Prefix for the keys of the exported files in the bucket.
+        #[arg(long, requires = "bucket")]
+        key_prefix: Option<String>,
+        /// Only export crates which changed after the export which printed this watermark.
+        ///
+        /// A watermark is printed after every export, for use in the next one.
+        #[arg(long)]
+        since: Option<u64>,
+    },
+    /// Render the stored sparse index entry of every crate again.
+    ///
+    /// Entries are rendered when crates are published or yanked, so this should be run after
+    /// upgrading to a version of freighter which renders them differently.
+    RebuildIndex,
+    /// Import crates from an existing registry.
+    ///
+    /// Versions which are already present in the index are skipped, so an interrupted import can
+    /// be resumed by running it again.
+    Import {
+        /// Directory containing a checkout of the registry's index, either sparse or git.
+        index_dir: PathBuf,
+        /// Directory containing the registry's crate files, named `{name}-{version}.crate`.
+        crates_dir: PathBuf,
+        /// Check the registry without importing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Mirror crates and their dependencies from another registry.
+    ///
+    /// Mirrored crates cannot be published to locally.
+    Mirror {
+        /// URL of the sparse index of the registry to mirror from, such as
+        /// `https://index.crates.io/`.
+        #[arg(long)]
+        source: String,
+        /// Mirror the registry packages locked in a `Cargo.lock`.
+        #[arg(long)]
+        lockfile: Option<PathBuf>,
+        /// Crates to mirror, as `name` for every version or `name@requirement`.
+        crates: Vec<String>,
+    },
+    /// Export crates and their dependencies as a bundle for use as a cargo local registry.
+    ExportBundle {
+        /// Directory to write the bundle into, or the file to write to with `--tarball`.
+        out: PathBuf,
+        /// Write the bundle as a gzipped tarball.
+        #[arg(long)]
+        tarball: bool,
+        /// Include the registry packages locked in a `Cargo.lock`.
+        #[arg(long)]
+        lockfile: Option<PathBuf>,
+        /// Crates to include, as `name` for every version or `name@requirement`.
+        crates: Vec<String>,
+    },
 }
@@ -0,0 +1,232 @@
+//! Resolution of the dependency closure of a set of crates.
+//!
+//! Explicitly requested crates include every matching version, while their dependencies are
+//! resolved with [`freighter_index::resolve`], as they are for dependency graphs.
+
+use anyhow::{bail, Context};
+use freighter_index::resolve::{all_features, Resolver};
+use freighter_index::CrateVersion;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+
+/// A crate to include, and optionally a requirement its versions must match.
+#[derive(Clone, Debug)]
+pub struct CrateSpec {
+    pub name: String,
+    pub req: Option<VersionReq>,
+}
+
+impl CrateSpec {
+    /// Parse a crate specification in the form `name` or `name@requirement`.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        match spec.split_once('@') {
+            Some((name, req)) => Ok(Self {
+                name: name.to_string(),
+                req: Some(
+                    VersionReq::parse(req)
+                        .with_context(|| format!("Invalid version requirement in {spec}"))?,
+                ),
+            }),
+            None => Ok(Self {
+                name: spec.to_string(),
+                req: None,
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: Version,
+    source: Option<String>,
+}
+
+/// Read the registry packages locked in a `Cargo.lock`, pinned to their exact versions.
+///
+/// Path and git dependencies are ignored.
+pub fn read_lockfile(path: &Path) -> anyhow::Result<Vec<CrateSpec>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let lockfile: Lockfile =
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    Ok(lockfile
+        .package
+        .into_iter()
+        .filter(|package| {
+            package.source.as_deref().is_some_and(|source| {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            })
+        })
+        .map(|package| CrateSpec {
+            req: Some(VersionReq {
+                comparators: vec![semver::Comparator {
+                    op: semver::Op::Exact,
+                    major: package.version.major,
+                    minor: Some(package.version.minor),
+                    patch: Some(package.version.patch),
+                    pre: package.version.pre.clone(),
+                }],
+            }),
+            name: package.name,
+        })
+        .collect())
+}
+
+/// Find every version needed to build the crates matching `specs`.
+///
+/// Requested versions are resolved with all of their features enabled, so that the closure
+/// covers any way they might be built.
+/// Dependencies from other registries can't be included, and are logged as warnings.
+///
+/// `fetch_entry` is used to look up the versions of a crate, and is called at most once per crate.
+pub async fn resolve_closure<F, Fut>(
+    specs: &[CrateSpec],
+    fetch_entry: F,
+) -> anyhow::Result<BTreeMap<(String, Version), CrateVersion>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<CrateVersion>>>,
+{
+    let mut resolver = Resolver::new(fetch_entry);
+
+    for spec in specs {
+        let matching: Vec<_> = resolver
+            .get_entry(&spec.name)
+            .await?
+            .iter()
+            .filter(|version| {
+                spec.req
+                    .as_ref()
+                    .is_none_or(|req| req.matches(&version.vers))
+            })
+            .cloned()
+            .collect();
+
+        if matching.is_empty() {
+            bail!("No version of {} matches the requested version", spec.name);
+        }
+
+        for version in matching {
+            let features = all_features(&version);
+
+            resolver.add_root(version, features);
+        }
+    }
+
+    let resolved = resolver.resolve().await?;
+
+    for (id, package) in &resolved {
+        for dependency in &package.unresolved {
+            tracing::warn!(
+                "Not including {} {} from {}, required by {} {}",
+                dependency.name,
+                dependency.req,
+                dependency.registry,
+                id.name,
+                id.version
+            );
+        }
+    }
+
+    Ok(resolved
+        .into_iter()
+        .map(|(id, package)| ((id.name, id.version), package.version))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// An index entry line without dependencies.
+    fn version(name: &str, vers: &str) -> CrateVersion {
+        serde_json::from_value(json!({
+            "name": name,
+            "vers": vers,
+            "deps": [],
+            "cksum": "0".repeat(64),
+            "features": {},
+            "yanked": false,
+            "links": null,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn requested_versions_are_filtered_by_requirement() {
+        let versions = vec![version("app", "1.0.0"), version("app", "2.0.0")];
+        let fetch = |_| async { Ok(versions.clone()) };
+
+        let closure = resolve_closure(&[CrateSpec::parse("app@^2").unwrap()], fetch)
+            .await
+            .unwrap();
+
+        let resolved: Vec<_> = closure
+            .keys()
+            .map(|(name, vers)| format!("{name}@{vers}"))
+            .collect();
+
+        assert_eq!(resolved, ["app@2.0.0"]);
+
+        let unmatched = resolve_closure(&[CrateSpec::parse("app@^3").unwrap()], fetch).await;
+
+        assert!(unmatched.is_err());
+    }
+
+    #[test]
+    fn lockfile_registry_packages_are_pinned() {
+        let path = std::env::temp_dir().join(format!("freighter-test-{}.lock", std::process::id()));
+
+        std::fs::write(
+            &path,
+            r#"
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+
+[[package]]
+name = "lib"
+version = "1.2.3-beta.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "0000000000000000000000000000000000000000000000000000000000000000"
+
+[[package]]
+name = "sparse-lib"
+version = "0.4.0"
+source = "sparse+https://example.com/index/"
+
+[[package]]
+name = "git-lib"
+version = "0.1.0"
+source = "git+https://example.com/git-lib#0123456789abcdef"
+"#,
+        )
+        .unwrap();
+
+        let specs = read_lockfile(&path);
+        std::fs::remove_file(&path).unwrap();
+        let specs = specs.unwrap();
+
+        let specs: Vec<_> = specs
+            .iter()
+            .map(|spec| format!("{}@{}", spec.name, spec.req.as_ref().unwrap()))
+            .collect();
+
+        assert_eq!(specs, ["lib@=1.2.3-beta.1", "sparse-lib@=0.4.0"]);
+    }
+}
@@ -1,5 +1,6 @@
 use freighter_server::ServiceConfig;
 use serde::Deserialize;
+use std::path::PathBuf;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -7,13 +8,32 @@ pub struct Config {
     pub index_db: deadpool_postgres::Config,
     pub auth_db: deadpool_postgres::Config,
     pub store: StoreConfig,
+    /// Approximate size in bytes of the in-memory sparse index entry cache.
+    ///
+    /// If not set, sparse index entries are not cached.
+    pub index_cache_size: Option<usize>,
 }
 
 #[derive(Deserialize)]
 pub struct StoreConfig {
     pub name: String,
-    pub endpoint_url: String,
+    /// Endpoint of the S3-compatible service, defaulting to the regional AWS endpoint.
+    pub endpoint_url: Option<String>,
     pub region: String,
-    pub access_key_id: String,
-    pub access_key_secret: String,
+    /// Use path-style bucket addressing, as required by MinIO and similar services.
+    #[serde(default)]
+    pub path_style: bool,
+    /// Prefix for every object key, for sharing a bucket between registries.
+    pub key_prefix: Option<String>,
+    /// Static access key id.
+    ///
+    /// If neither this nor `access_key_secret` is set, the standard AWS credential chain is used.
+    pub access_key_id: Option<String>,
+    pub access_key_secret: Option<String>,
+    /// Session token to use alongside temporary static credentials.
+    pub session_token: Option<String>,
+    /// Path to a key file used to encrypt crates before they are stored.
+    ///
+    /// If not set, crates are stored unencrypted.
+    pub encryption_key_file: Option<PathBuf>,
 }
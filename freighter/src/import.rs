@@ -0,0 +1,465 @@
+//! Import of crates from an existing registry.
+//!
+//! The source registry is read from disk: an index checkout, which may be either a sparse index
+//! tree or a clone of a git index since both share the same layout, and a directory containing
+//! the `.crate` files as `{name}-{version}.crate`.
+//!
+//! Every version is replayed through [`IndexProvider::publish`] and
+//! [`StorageProvider::put_crate`], using the checksum from the source index after verifying it
+//! against the `.crate` file, and yanked afterwards if it was yanked in the source index.
+//! Versions which are already present are skipped, so an interrupted import can be resumed by
+//! running it again.
+//!
+//! Imported crates have no owners, so the first user to publish a new version of one will become
+//! its owner.
+
+use anyhow::{bail, Context};
+use freighter_index::{
+    CrateVersion, Dependency, IndexError, IndexProvider, Publish, PublishDependency,
+};
+use freighter_storage::StorageProvider;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// Versions which were published, or would have been in a dry run.
+    pub published: usize,
+    /// Versions which were yanked, or would have been in a dry run.
+    pub yanked: usize,
+    /// Versions which were already present in the index.
+    pub skipped: usize,
+}
+
+/// Import every crate in `index_dir`, reading the crate files from `crates_dir`.
+///
+/// If `dry_run` is set, the source registry is read and checked in full, but nothing is written.
+pub async fn import_registry<I, S>(
+    index: &I,
+    storage: &S,
+    index_dir: &Path,
+    crates_dir: &Path,
+    dry_run: bool,
+) -> anyhow::Result<ImportSummary>
+where
+    I: IndexProvider + Sync,
+    S: StorageProvider + Clone + Send + Sync + 'static,
+{
+    let mut entries = Vec::new();
+
+    find_entries(index_dir, &mut entries)?;
+
+    entries.sort();
+
+    let mut summary = ImportSummary::default();
+
+    for path in entries {
+        let versions = read_entry(&path)?;
+
+        let Some(crate_name) = versions.first().map(|version| version.name.clone()) else {
+            continue;
+        };
+
+        let existing: HashMap<_, _> = match index.get_sparse_entry(&crate_name).await {
+            Ok(existing) => existing
+                .into_iter()
+                .map(|version| (version.vers, version.yanked))
+                .collect(),
+            Err(IndexError::NotFound) => HashMap::new(),
+            Err(error) => {
+                return Err(error).context(format!("Failed to fetch index entry for {crate_name}"))
+            }
+        };
+
+        for version in versions {
+            match existing.get(&version.vers) {
+                Some(&already_yanked) => {
+                    // the import may have been interrupted between publishing and yanking
+                    if version.yanked && !already_yanked {
+                        yank(index, &version, dry_run).await?;
+
+                        summary.yanked += 1;
+                    } else {
+                        summary.skipped += 1;
+                    }
+                }
+                None => {
+                    publish(index, storage, crates_dir, &version, dry_run).await?;
+
+                    summary.published += 1;
+
+                    if version.yanked {
+                        yank(index, &version, dry_run).await?;
+
+                        summary.yanked += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn publish<I, S>(
+    index: &I,
+    storage: &S,
+    crates_dir: &Path,
+    version: &CrateVersion,
+    dry_run: bool,
+) -> anyhow::Result<()>
+where
+    I: IndexProvider + Sync,
+    S: StorageProvider + Clone + Send + Sync + 'static,
+{
+    let name = version.name.clone();
+    let vers = version.vers.to_string();
+
+    let crate_path = crates_dir.join(format!("{name}-{vers}.crate"));
+
+    let crate_bytes = fs::read(&crate_path)
+        .with_context(|| format!("Failed to read {}", crate_path.display()))?;
+
+    let checksum = format!("{:x}", Sha256::digest(&crate_bytes));
+
+    if checksum != version.cksum {
+        bail!(
+            "Checksum of {} does not match the index, expected {} but found {checksum}",
+            crate_path.display(),
+            version.cksum
+        );
+    }
+
+    if dry_run {
+        tracing::info!(crate_name = name, version = vers, "Would publish");
+
+        return Ok(());
+    }
+
+    let storage = storage.clone();
+
+    let end_step = {
+        let (name, vers) = (name.clone(), vers.clone());
+
+        Box::pin(async move {
+            storage
+                .put_crate(&name, &vers, &crate_bytes)
+                .await
+                .context("Failed to store crate in storage medium")
+        })
+    };
+
+    index
+        .publish(&to_publish(version), &checksum, None, end_step)
+        .await
+        .with_context(|| format!("Failed to publish {name} {vers}"))?;
+
+    tracing::info!(crate_name = name, version = vers, "Published");
+
+    Ok(())
+}
+
+async fn yank<I>(index: &I, version: &CrateVersion, dry_run: bool) -> anyhow::Result<()>
+where
+    I: IndexProvider + Sync,
+{
+    let name = &version.name;
+    let vers = version.vers.to_string();
+
+    if !dry_run {
+        index
+            .yank_crate(name, &version.vers)
+            .await
+            .with_context(|| format!("Failed to yank {name} {vers}"))?;
+    }
+
+    tracing::info!(crate_name = name, version = vers, dry_run, "Yanked");
+
+    Ok(())
+}
+
+/// Recursively find every index entry in a directory, skipping `config.json` and hidden files
+/// such as the `.git` directory.
+fn find_entries(dir: &Path, entries: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let read_dir =
+        fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    for dir_entry in read_dir {
+        let dir_entry = dir_entry.context("Failed to read directory entry")?;
+        let file_name = dir_entry.file_name();
+
+        if file_name.to_string_lossy().starts_with('.') || file_name == "config.json" {
+            continue;
+        }
+
+        let path = dir_entry.path();
+
+        if dir_entry
+            .file_type()
+            .context("Failed to read file type")?
+            .is_dir()
+        {
+            find_entries(&path, entries)?;
+        } else {
+            entries.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_entry(path: &Path) -> anyhow::Result<Vec<CrateVersion>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse index entry {}", path.display()))
+        })
+        .collect()
+}
+
+/// Reconstruct the publish request which would have produced an index entry.
+///
+/// Metadata which isn't part of the index, such as the description, is left empty.
+pub fn to_publish(version: &CrateVersion) -> Publish {
+    let mut features = version.features.clone();
+    features.extend(version.features2.clone());
+
+    Publish {
+        name: version.name.clone(),
+        vers: version.vers.clone(),
+        deps: version.deps.iter().map(to_publish_dependency).collect(),
+        features,
+        authors: Vec::new(),
+        description: None,
+        documentation: None,
+        homepage: None,
+        readme: None,
+        readme_file: None,
+        keywords: Vec::new(),
+        categories: Vec::new(),
+        license: None,
+        license_file: None,
+        repository: None,
+        badges: None,
+        links: version.links.clone(),
+        rust_version: version.rust_version.clone(),
+    }
+}
+
+fn to_publish_dependency(dependency: &Dependency) -> PublishDependency {
+    // in the index, renamed dependencies are listed under their new name
+    let (name, explicit_name_in_toml) = match &dependency.package {
+        Some(package) => (package.clone(), Some(dependency.name.clone())),
+        None => (dependency.name.clone(), None),
+    };
+
+    PublishDependency {
+        name,
+        version_req: dependency.req.clone(),
+        features: dependency.features.clone(),
+        optional: dependency.optional,
+        default_features: dependency.default_features,
+        target: dependency.target.clone(),
+        kind: dependency.kind.clone(),
+        registry: dependency.registry.clone(),
+        explicit_name_in_toml,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need the database described in
+    //! [`freighter_test_support::index_db_config`].
+
+    use super::*;
+    use freighter_index::postgres_client::PgIndexProvider;
+    use freighter_index::sparse_entry_path;
+    use freighter_test_support::{index_db_config, unique_name, MemoryStorage};
+    use semver::Version;
+    use serde_json::json;
+
+    fn test_index() -> PgIndexProvider {
+        PgIndexProvider::new(index_db_config()).unwrap()
+    }
+
+    /// A source registry on disk, with an index and crates directory.
+    struct Source {
+        dir: PathBuf,
+    }
+
+    impl Source {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(unique_name("freighter-import"));
+
+            fs::create_dir_all(dir.join("index")).unwrap();
+            fs::create_dir_all(dir.join("crates")).unwrap();
+            fs::write(dir.join("index/config.json"), "{}").unwrap();
+
+            Self { dir }
+        }
+
+        fn index_dir(&self) -> PathBuf {
+            self.dir.join("index")
+        }
+
+        fn crates_dir(&self) -> PathBuf {
+            self.dir.join("crates")
+        }
+
+        /// Write the index entry of a crate with the given versions and yank status, along with
+        /// their crate files.
+        fn write(&self, name: &str, versions: &[(&str, bool)]) {
+            let mut entry = String::new();
+
+            for (vers, yanked) in versions {
+                let contents = format!("the contents of {name} {vers}");
+
+                let line = json!({
+                    "name": name,
+                    "vers": vers,
+                    "deps": [],
+                    "cksum": format!("{:x}", Sha256::digest(&contents)),
+                    "features": {},
+                    "yanked": yanked,
+                    "links": null,
+                });
+
+                entry.push_str(&format!("{line}\n"));
+
+                fs::write(
+                    self.crates_dir().join(format!("{name}-{vers}.crate")),
+                    contents,
+                )
+                .unwrap();
+            }
+
+            let path = self.index_dir().join(sparse_entry_path(name));
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, entry).unwrap();
+        }
+
+        async fn import(
+            &self,
+            index: &PgIndexProvider,
+            storage: &MemoryStorage,
+            dry_run: bool,
+        ) -> anyhow::Result<ImportSummary> {
+            import_registry(
+                index,
+                storage,
+                &self.index_dir(),
+                &self.crates_dir(),
+                dry_run,
+            )
+            .await
+        }
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn v(vers: &str) -> Version {
+        Version::parse(vers).unwrap()
+    }
+
+    fn counts(summary: &ImportSummary) -> (usize, usize, usize) {
+        (summary.published, summary.yanked, summary.skipped)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs FREIGHTER_TEST_INDEX_DB"]
+    async fn dry_run_writes_nothing() {
+        let index = test_index();
+        let storage = MemoryStorage::default();
+        let source = Source::new();
+        let name = unique_name("import-dry");
+
+        source.write(&name, &[("1.0.0", false), ("1.1.0", true)]);
+
+        let summary = source.import(&index, &storage, true).await.unwrap();
+
+        assert_eq!(counts(&summary), (2, 1, 0));
+        assert!(matches!(
+            index.get_sparse_entry(&name).await,
+            Err(IndexError::NotFound)
+        ));
+        assert!(storage.keys().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs FREIGHTER_TEST_INDEX_DB"]
+    async fn imports_resume_and_apply_yanks() {
+        let index = test_index();
+        let storage = MemoryStorage::default();
+        let source = Source::new();
+        let name = unique_name("import-resume");
+
+        source.write(&name, &[("1.0.0", false), ("1.1.0", false)]);
+
+        let summary = source.import(&index, &storage, false).await.unwrap();
+
+        assert_eq!(counts(&summary), (2, 0, 0));
+        assert_eq!(
+            storage.keys(),
+            [format!("{name}-1.0.0.crate"), format!("{name}-1.1.0.crate")]
+        );
+
+        // a version yanked in the source since, as if the import was interrupted before yanking
+        source.write(
+            &name,
+            &[("1.0.0", false), ("1.1.0", true), ("1.2.0", false)],
+        );
+
+        let summary = source.import(&index, &storage, false).await.unwrap();
+
+        assert_eq!(counts(&summary), (1, 1, 1));
+        assert!(!index.confirm_existence(&name, &v("1.0.0")).await.unwrap());
+        assert!(index.confirm_existence(&name, &v("1.1.0")).await.unwrap());
+        assert!(!index.confirm_existence(&name, &v("1.2.0")).await.unwrap());
+
+        // once complete, importing again changes nothing
+        let summary = source.import(&index, &storage, false).await.unwrap();
+
+        assert_eq!(counts(&summary), (0, 0, 3));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs FREIGHTER_TEST_INDEX_DB"]
+    async fn checksum_mismatch_is_rejected() {
+        let index = test_index();
+        let storage = MemoryStorage::default();
+        let source = Source::new();
+        let name = unique_name("import-checksum");
+
+        source.write(&name, &[("1.0.0", false)]);
+
+        fs::write(
+            source.crates_dir().join(format!("{name}-1.0.0.crate")),
+            "some other contents",
+        )
+        .unwrap();
+
+        for dry_run in [true, false] {
+            let error = source.import(&index, &storage, dry_run).await.unwrap_err();
+
+            assert!(error.to_string().contains("Checksum"), "{error}");
+        }
+
+        assert!(matches!(
+            index.get_sparse_entry(&name).await,
+            Err(IndexError::NotFound)
+        ));
+        assert!(storage.keys().is_empty());
+    }
+}
@@ -1,13 +1,29 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use axum::Router;
 use clap::Parser;
 use freighter_auth::pg_backend::PgAuthProvider;
+use freighter_index::caching::{CacheInvalidator, CachingIndexProvider};
+use freighter_index::export::{export_sparse_index, DirectoryTarget, ExportTarget};
 use freighter_index::postgres_client::PgIndexProvider;
-use freighter_storage::s3_client::S3StorageProvider;
+use freighter_index::{IndexProvider, RegistryConfig};
+use freighter_server::downloads::DownloadRecorder;
+use freighter_server::upstream::{Upstream, UpstreamConfig};
+use freighter_server::ServiceConfig;
+use freighter_storage::encrypted::{EncryptedStorageProvider, KeyRing};
+use freighter_storage::s3_client::{S3Config, S3Credentials, S3StorageProvider};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::fs::read_to_string;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::oneshot;
 
+mod bundle;
 mod cli;
+mod closure;
 mod config;
+mod import;
+mod mirror;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -26,8 +42,73 @@ async fn main() -> anyhow::Result<()> {
         index_db,
         auth_db,
         store,
+        index_cache_size,
     } = config;
 
+    let index_client =
+        PgIndexProvider::new(index_db).context("Failed to construct index client")?;
+
+    match args.command {
+        None => serve(service, index_client, auth_db, store, index_cache_size).await,
+        Some(cli::Command::ExportIndex {
+            out_dir,
+            bucket,
+            key_prefix,
+            since,
+        }) => {
+            if let Some(bucket) = bucket {
+                let store = config::StoreConfig {
+                    name: bucket,
+                    key_prefix,
+                    ..store
+                };
+                let (storage_client, _) = storage_client(store).await?;
+
+                export_index(service, index_client, &BucketTarget(storage_client), since).await
+            } else {
+                // clap requires one of the two
+                let out_dir = out_dir.unwrap();
+
+                export_index(service, index_client, &DirectoryTarget::new(out_dir), since).await
+            }
+        }
+        Some(cli::Command::RebuildIndex) => rebuild_index(index_client).await,
+        Some(cli::Command::Import {
+            index_dir,
+            crates_dir,
+            dry_run,
+        }) => import(index_client, store, &index_dir, &crates_dir, dry_run).await,
+        Some(cli::Command::Mirror {
+            source,
+            lockfile,
+            crates,
+        }) => mirror(index_client, store, source, lockfile.as_deref(), &crates).await,
+        Some(cli::Command::ExportBundle {
+            out,
+            tarball,
+            lockfile,
+            crates,
+        }) => {
+            export_bundle(
+                index_client,
+                store,
+                &out,
+                tarball,
+                lockfile.as_deref(),
+                &crates,
+            )
+            .await
+        }
+    }
+}
+
+async fn serve(
+    service: ServiceConfig,
+    index_client: PgIndexProvider,
+    auth_db: deadpool_postgres::Config,
+    store: config::StoreConfig,
+    index_cache_size: Option<usize>,
+) -> anyhow::Result<()> {
     PrometheusBuilder::new()
         .add_global_label("service", "freighter")
         .with_http_listener(service.metrics_address)
@@ -41,23 +122,324 @@ async fn main() -> anyhow::Result<()> {
 
     let addr = service.address;
 
-    let index_client =
-        PgIndexProvider::new(index_db).context("Failed to construct index client")?;
-    let storage_client = S3StorageProvider::new(
-        &store.name,
-        &store.endpoint_url,
-        &store.region,
-        &store.access_key_id,
-        &store.access_key_secret,
-    );
+    let (storage_client, keys) = storage_client(store).await?;
     let auth_client = PgAuthProvider::new(auth_db).context("Failed to initialize auth client")?;
 
-    let router = freighter_server::router(service, index_client, storage_client, auth_client);
+    let (router, download_recorder) = if let Some(index_cache_size) = index_cache_size {
+        tracing::info!(index_cache_size, "Caching sparse index entries");
+
+        let changes_client = index_client.clone();
+        let index_client = CachingIndexProvider::new(index_client, index_cache_size);
+
+        tokio::spawn(follow_index_changes(
+            changes_client,
+            index_client.invalidator(),
+        ));
+
+        build_router(service, index_client, storage_client, keys, auth_client)
+    } else {
+        build_router(service, index_client, storage_client, keys, auth_client)
+    };
+
+    // downloads are recorded until the server has stopped serving crates, and then once more
+    let (stop_recording, recording_stopped) = oneshot::channel::<()>();
+    let download_recorder = tokio::spawn(download_recorder.run(async {
+        let _ = recording_stopped.await;
+    }));
 
     tracing::info!(?addr, "Starting freighter instance");
 
-    axum::Server::bind(&addr)
+    let res = axum::Server::bind(&addr)
         .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .context("Freighter server exited with error");
+
+    let _ = stop_recording.send(());
+
+    download_recorder
         .await
-        .context("Freighter server exited with error")
+        .context("Failed to record remaining downloads")?;
+
+    res
+}
+
+/// Wait for a request to shut down, from either Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            tracing::error!(?error, "Failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(error) => {
+                tracing::error!(?error, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("Shutting down");
+}
+
+/// Export target writing into a bucket.
+struct BucketTarget(S3StorageProvider);
+
+#[async_trait]
+impl ExportTarget for BucketTarget {
+    async fn write_file(&self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        // objects are replaced atomically
+        self.0.put_file(path, contents).await?;
+
+        Ok(())
+    }
+}
+
+async fn export_index<T>(
+    service: ServiceConfig,
+    index_client: PgIndexProvider,
+    target: &T,
+    since: Option<u64>,
+) -> anyhow::Result<()>
+where
+    T: ExportTarget + Sync,
+{
+    let registry_config = RegistryConfig {
+        dl: service.download_endpoint,
+        api: service.api_endpoint,
+    };
+
+    let summary = export_sparse_index(&index_client, &registry_config, target, since)
+        .await
+        .context("Failed to export index")?;
+
+    tracing::info!(entries = summary.entries, "Exported index");
+
+    // print the watermark to resume from, so that it can be captured by scripts
+    println!("{}", summary.watermark);
+
+    Ok(())
+}
+
+async fn rebuild_index(index_client: PgIndexProvider) -> anyhow::Result<()> {
+    let changed = index_client
+        .rematerialize_sparse_entries()
+        .await
+        .context("Failed to rebuild index")?;
+
+    tracing::info!(changed, "Rebuilt index");
+
+    Ok(())
+}
+
+async fn import(
+    index_client: PgIndexProvider,
+    store: config::StoreConfig,
+    index_dir: &Path,
+    crates_dir: &Path,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let (storage_client, keys) = storage_client(store).await?;
+
+    let summary = if let Some(keys) = keys {
+        let storage_client = EncryptedStorageProvider::new(storage_client, keys);
+
+        import::import_registry(
+            &index_client,
+            &storage_client,
+            index_dir,
+            crates_dir,
+            dry_run,
+        )
+        .await?
+    } else {
+        import::import_registry(
+            &index_client,
+            &storage_client,
+            index_dir,
+            crates_dir,
+            dry_run,
+        )
+        .await?
+    };
+
+    tracing::info!(
+        published = summary.published,
+        yanked = summary.yanked,
+        skipped = summary.skipped,
+        dry_run,
+        "Import complete"
+    );
+
+    Ok(())
+}
+
+async fn mirror(
+    index_client: PgIndexProvider,
+    store: config::StoreConfig,
+    source: String,
+    lockfile: Option<&Path>,
+    crates: &[String],
+) -> anyhow::Result<()> {
+    let specs = crate_specs(lockfile, crates)?;
+
+    let upstream = Upstream::new(&UpstreamConfig {
+        index_url: source.clone(),
+        entry_ttl_secs: 3600,
+    });
+
+    let (storage_client, keys) = storage_client(store).await?;
+
+    let summary = if let Some(keys) = keys {
+        let storage_client = EncryptedStorageProvider::new(storage_client, keys);
+
+        mirror::mirror_crates(&index_client, &storage_client, &upstream, &source, &specs).await?
+    } else {
+        mirror::mirror_crates(&index_client, &storage_client, &upstream, &source, &specs).await?
+    };
+
+    tracing::info!(
+        mirrored = summary.mirrored,
+        skipped = summary.skipped,
+        "Mirror complete"
+    );
+
+    Ok(())
+}
+
+async fn export_bundle(
+    index_client: PgIndexProvider,
+    store: config::StoreConfig,
+    out: &Path,
+    tarball: bool,
+    lockfile: Option<&Path>,
+    crates: &[String],
+) -> anyhow::Result<()> {
+    let specs = crate_specs(lockfile, crates)?;
+
+    let (storage_client, keys) = storage_client(store).await?;
+
+    let summary = if let Some(keys) = keys {
+        let storage_client = EncryptedStorageProvider::new(storage_client, keys);
+
+        bundle::export_bundle(&index_client, &storage_client, &specs, out, tarball).await?
+    } else {
+        bundle::export_bundle(&index_client, &storage_client, &specs, out, tarball).await?
+    };
+
+    tracing::info!(
+        crates = summary.crates,
+        versions = summary.versions,
+        "Exported bundle"
+    );
+
+    Ok(())
+}
+
+/// Collect the crates given on the command line and in a lockfile.
+fn crate_specs(
+    lockfile: Option<&Path>,
+    crates: &[String],
+) -> anyhow::Result<Vec<closure::CrateSpec>> {
+    let mut specs = crates
+        .iter()
+        .map(|spec| closure::CrateSpec::parse(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if let Some(lockfile) = lockfile {
+        specs.extend(closure::read_lockfile(lockfile)?);
+    }
+
+    if specs.is_empty() {
+        bail!("No crates given, pass either crate names or a lockfile");
+    }
+
+    Ok(specs)
+}
+
+async fn storage_client(
+    store: config::StoreConfig,
+) -> anyhow::Result<(S3StorageProvider, Option<KeyRing>)> {
+    let credentials = match (store.access_key_id, store.access_key_secret) {
+        (Some(access_key_id), Some(secret_access_key)) => S3Credentials::Static {
+            access_key_id,
+            secret_access_key,
+            session_token: store.session_token,
+        },
+        (None, None) if store.session_token.is_some() => {
+            bail!("session_token can only be set along with access_key_id and access_key_secret")
+        }
+        (None, None) => S3Credentials::DefaultChain,
+        _ => bail!("Both or neither of access_key_id and access_key_secret must be set"),
+    };
+    let storage_client = S3StorageProvider::from_config(S3Config {
+        bucket_name: store.name,
+        endpoint_url: store.endpoint_url,
+        region: store.region,
+        path_style: store.path_style,
+        key_prefix: store.key_prefix,
+        credentials,
+    })
+    .await;
+
+    let keys = store
+        .encryption_key_file
+        .as_ref()
+        .map(KeyRing::from_file)
+        .transpose()
+        .context("Failed to load encryption keys")?;
+
+    Ok((storage_client, keys))
+}
+
+/// Keep the index cache coherent with changes made by other freighter instances.
+async fn follow_index_changes(index_client: PgIndexProvider, invalidator: CacheInvalidator) {
+    loop {
+        let res = match index_client.subscribe().await {
+            Ok(changes) => invalidator.follow(changes).await,
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = res {
+            tracing::error!(?error, "Lost index change subscription, retrying");
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn build_router<I>(
+    service: ServiceConfig,
+    index_client: I,
+    storage_client: S3StorageProvider,
+    keys: Option<KeyRing>,
+    auth_client: PgAuthProvider,
+) -> (Router, DownloadRecorder)
+where
+    I: IndexProvider + Send + Sync + 'static,
+{
+    if let Some(keys) = keys {
+        tracing::info!(key_id = keys.active_key_id(), "Encrypting stored crates");
+
+        let storage_client = EncryptedStorageProvider::new(storage_client, keys);
+
+        freighter_server::router(service, index_client, storage_client, auth_client)
+    } else {
+        freighter_server::router(service, index_client, storage_client, auth_client)
+    }
 }
@@ -0,0 +1,263 @@
+//! Mirroring of selected crates from another registry.
+//!
+//! Starting from a set of crates, either given directly or taken from a `Cargo.lock`, the index
+//! entries of the source registry are walked to find everything needed to build them, as
+//! described in [the closure module](crate::closure).
+//!
+//! Each version is downloaded, verified against the checksum in the source index, and published
+//! with [`IndexProvider::publish_mirrored`] using that same checksum, so that existing lockfiles
+//! remain valid.
+//! Versions which are already present are skipped, so mirroring can be rerun to pick up
+//! additions.
+
+use crate::closure::{resolve_closure, CrateSpec};
+use crate::import::to_publish;
+use anyhow::Context;
+use freighter_index::{CrateVersion, IndexError, IndexProvider};
+use freighter_server::upstream::Upstream;
+use freighter_storage::StorageProvider;
+
+#[derive(Debug, Default)]
+pub struct MirrorSummary {
+    /// Versions which were mirrored.
+    pub mirrored: usize,
+    /// Versions which were already present in the index.
+    pub skipped: usize,
+}
+
+/// Mirror the crates matching `specs`, along with their dependencies, from `upstream`.
+///
+/// `source` is recorded as the registry the crates were mirrored from.
+pub async fn mirror_crates<I, S>(
+    index: &I,
+    storage: &S,
+    upstream: &Upstream,
+    source: &str,
+    specs: &[CrateSpec],
+) -> anyhow::Result<MirrorSummary>
+where
+    I: IndexProvider + Sync,
+    S: StorageProvider + Clone + Send + Sync + 'static,
+{
+    let closure = resolve_closure(specs, |crate_name| {
+        fetch_entry(upstream, storage, crate_name)
+    })
+    .await?;
+
+    tracing::info!(versions = closure.len(), "Resolved crates to mirror");
+
+    let mut summary = MirrorSummary::default();
+
+    for version in closure.values() {
+        let name = &version.name;
+        let vers = version.vers.to_string();
+
+        match index.confirm_existence(name, &version.vers).await {
+            Ok(_) => {
+                summary.skipped += 1;
+
+                continue;
+            }
+            Err(IndexError::NotFound) => {}
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to check for {name} {vers} in index"))
+            }
+        }
+
+        let crate_bytes = upstream
+            .get_crate(storage, name, &version.vers)
+            .await?
+            .with_context(|| format!("Source registry has no crate file for {name} {vers}"))?;
+
+        let end_step = {
+            let storage = storage.clone();
+            let (name, vers) = (name.clone(), vers.clone());
+
+            Box::pin(async move {
+                storage
+                    .put_crate(&name, &vers, &crate_bytes)
+                    .await
+                    .context("Failed to store crate in storage medium")
+            })
+        };
+
+        index
+            .publish_mirrored(&to_publish(version), &version.cksum, source, end_step)
+            .await
+            .with_context(|| format!("Failed to mirror {name} {vers}"))?;
+
+        if version.yanked {
+            index
+                .yank_crate(name, &version.vers)
+                .await
+                .with_context(|| format!("Failed to yank {name} {vers}"))?;
+        }
+
+        tracing::info!(crate_name = name, version = vers, "Mirrored");
+
+        summary.mirrored += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Fetch and parse the index entry of a crate from the source registry.
+async fn fetch_entry<S>(
+    upstream: &Upstream,
+    storage: &S,
+    crate_name: String,
+) -> anyhow::Result<Vec<CrateVersion>>
+where
+    S: StorageProvider + Sync,
+{
+    let entry = upstream
+        .get_entry(storage, &crate_name)
+        .await?
+        .with_context(|| format!("Source registry has no crate named {crate_name}"))?;
+
+    String::from_utf8_lossy(&entry)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<CrateVersion>, _>>()
+        .with_context(|| format!("Failed to parse index entry for {crate_name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need the database described in
+    //! [`freighter_test_support::index_db_config`].
+
+    use super::*;
+    use axum::body::Bytes;
+    use freighter_index::postgres_client::PgIndexProvider;
+    use freighter_server::upstream::UpstreamConfig;
+    use freighter_test_support::{index_db_config, unique_name, MemoryStorage, StandInRegistry};
+    use semver::Version;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+
+    fn test_index() -> PgIndexProvider {
+        PgIndexProvider::new(index_db_config()).unwrap()
+    }
+
+    fn crate_bytes(name: &str, vers: &str) -> String {
+        format!("the contents of {name} {vers}")
+    }
+
+    fn entry_line(name: &str, vers: &str, yanked: bool, deps: &[&str]) -> String {
+        let line = json!({
+            "name": name,
+            "vers": vers,
+            "deps": deps
+                .iter()
+                .map(|dep| json!({
+                    "name": dep,
+                    "req": "^1",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "normal",
+                    "registry": null,
+                    "package": null,
+                }))
+                .collect::<Vec<_>>(),
+            "cksum": format!("{:x}", Sha256::digest(crate_bytes(name, vers))),
+            "features": {},
+            "yanked": yanked,
+            "links": null,
+        });
+
+        format!("{line}\n")
+    }
+
+    #[tokio::test]
+    #[ignore = "needs FREIGHTER_TEST_INDEX_DB"]
+    async fn crates_are_mirrored_with_their_dependencies() {
+        let index = test_index();
+
+        let app = unique_name("mirror-app");
+        let lib = unique_name("mirror-lib");
+
+        let entries = HashMap::from([
+            (
+                app.clone(),
+                entry_line(&app, "0.9.0", true, &[]) + &entry_line(&app, "1.0.0", false, &[&lib]),
+            ),
+            (
+                lib.clone(),
+                entry_line(&lib, "1.0.0", false, &[]) + &entry_line(&lib, "1.1.0", true, &[]),
+            ),
+        ]);
+
+        let crates = [
+            (&app, "0.9.0"),
+            (&app, "1.0.0"),
+            (&lib, "1.0.0"),
+            (&lib, "1.1.0"),
+        ]
+        .into_iter()
+        .map(|(name, vers)| {
+            let key = (name.clone(), vers.to_string());
+
+            (key, Bytes::from(crate_bytes(name, vers)))
+        })
+        .collect();
+
+        let stand_in = StandInRegistry::start(entries, crates);
+        let source = stand_in.index_url();
+        let upstream = Upstream::new(&UpstreamConfig {
+            index_url: source.clone(),
+            entry_ttl_secs: 300,
+        });
+        let storage = MemoryStorage::default();
+        let specs = [CrateSpec::parse(&app).unwrap()];
+
+        let summary = mirror_crates(&index, &storage, &upstream, &source, &specs)
+            .await
+            .unwrap();
+
+        assert_eq!((summary.mirrored, summary.skipped), (3, 0));
+
+        // requested versions keep their yank status, dependencies skip yanked versions
+        let v = |vers: &str| Version::parse(vers).unwrap();
+        assert!(index.confirm_existence(&app, &v("0.9.0")).await.unwrap());
+        assert!(!index.confirm_existence(&app, &v("1.0.0")).await.unwrap());
+        assert!(!index.confirm_existence(&lib, &v("1.0.0")).await.unwrap());
+        assert!(matches!(
+            index.confirm_existence(&lib, &v("1.1.0")).await,
+            Err(IndexError::NotFound)
+        ));
+
+        // the source checksum is kept, so existing lockfiles remain valid
+        let entry = index.get_sparse_entry(&lib).await.unwrap();
+        assert_eq!(
+            entry[0].cksum,
+            format!("{:x}", Sha256::digest(crate_bytes(&lib, "1.0.0")))
+        );
+        assert_eq!(
+            storage.pull_crate(&lib, "1.0.0").await.unwrap(),
+            crate_bytes(&lib, "1.0.0")
+        );
+
+        // mirroring again skips everything
+        let summary = mirror_crates(&index, &storage, &upstream, &source, &specs)
+            .await
+            .unwrap();
+
+        assert_eq!((summary.mirrored, summary.skipped), (0, 3));
+
+        // mirrored crates can't be published to locally
+        let mut publish = to_publish(&entry[0]);
+        publish.vers = v("2.0.0");
+
+        let res = index
+            .publish(&publish, &entry[0].cksum, None, Box::pin(async { Ok(()) }))
+            .await;
+
+        assert!(matches!(res, Err(IndexError::Conflict(_))));
+    }
+}
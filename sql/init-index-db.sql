@@ -1,3 +1,7 @@
+-- revisions are drawn from a single sequence, so that they are unique across all crates
+drop sequence if exists crate_revisions cascade;
+create sequence crate_revisions;
+
 drop table if exists crates cascade;
 create table crates
 (
@@ -8,6 +12,14 @@ create table crates
     documentation text,
     homepage      text,
     repository    text,
+    mirrored_from text,
+    search_vector tsvector,
+    downloads     bigint      not null default 0,
+    revision      bigint      not null default 0,
+    -- the transaction which last changed the crate's sparse entry, for finding changes by commit
+    changed_xid   xid8        not null default '0',
+    created_at    timestamptz not null default now(),
+    updated_at    timestamptz not null default now(),
     unique nulls not distinct (name, registry)
 );
 
@@ -44,15 +56,35 @@ create table crate_categories
 drop table if exists crate_versions cascade;
 create table crate_versions
 (
-    id      integer primary key generated always as identity,
-    crate   integer not null references crates (id),
-    version text    not null,
-    cksum   text    not null,
-    yanked  bool    not null default false,
-    links   text,
+    id           integer primary key generated always as identity,
+    crate        integer     not null references crates (id),
+    version      text        not null,
+    cksum        text        not null,
+    yanked       bool        not null default false,
+    links        text,
+    rust_version text,
+    description  text,
+    license      text,
+    license_file text,
+    authors      text[]      not null default '{}',
+    readme       text,
+    readme_file  text,
+    published_by text,
+    downloads    bigint      not null default 0,
+    created_at   timestamptz not null default now(),
+    updated_at   timestamptz not null default now(),
     unique (crate, version)
 );
 
+drop table if exists version_downloads cascade;
+create table version_downloads
+(
+    version   integer not null references crate_versions (id),
+    date      date    not null,
+    downloads bigint  not null,
+    primary key (version, date)
+);
+
 drop table if exists features cascade;
 create table features
 (
@@ -60,6 +92,8 @@ create table features
     crate_version integer not null references crate_versions (id),
     name          text    not null,
     values        text[]  not null,
+    -- whether the feature uses syntax which must go in `features2` of the sparse entry
+    extended      bool    not null default false,
     unique (crate_version, name)
 );
 
@@ -81,11 +115,22 @@ create table dependencies
     package          text
 );
 
+-- rendered sparse index entries, maintained alongside the tables above on every publish and yank
+drop table if exists sparse_entries cascade;
+create table sparse_entries
+(
+    name  text primary key,
+    crate integer not null unique references crates (id),
+    entry text    not null
+);
+
 create index crate_keyword_crate on crate_keywords (crate);
 create index crate_keyword_keyword on crate_keywords (keyword);
 create index crate_categories_crate on crate_keywords (crate);
 create index crate_categories_category on crate_categories (category);
 create index crates_name_index on crates (name);
+create index crates_search_index on crates using gin (search_vector);
 create index crate_versions_crate_index on crate_versions (crate);
 create index features_index on features (crate_version);
-create index dependencies_dependent_index on dependencies (dependent);
\ No newline at end of file
+create index dependencies_dependent_index on dependencies (dependent);
+create index dependencies_dependency_index on dependencies (dependency);
\ No newline at end of file